@@ -1,29 +1,93 @@
 use anyhow::Context;
 
+use crate::config::Config;
+use crate::Command;
+
 mod clean;
 mod clone;
+mod hooks;
 mod init;
+mod install;
 mod link;
+mod push;
+mod restore;
+mod status;
 mod sync;
 
+pub use sync::PullStrategy;
+
 pub trait Exec {
-    fn execute(&self) -> anyhow::Result<()>;
+    fn execute(&self, config: &Config) -> anyhow::Result<()>;
 }
 
-impl Exec for crate::Command {
-    fn execute(&self) -> anyhow::Result<()> {
-        match self {
-            Self::Link { directory } => link::execute(directory),
-            Self::Clean { directory } => clean::execute(directory),
-            Self::Init => {
+impl Exec for crate::Args {
+    fn execute(&self, config: &Config) -> anyhow::Result<()> {
+        let dry_run = self.dry_run;
+        let no_hooks = self.no_hooks;
+        let or_default =
+            |directory: &Option<String>| directory.clone().unwrap_or_else(|| config.directory());
+
+        match &self.command {
+            Command::Link {
+                directory,
+                clone_dir,
+                force,
+                profile,
+            } => link::execute(
+                directory.as_deref(),
+                clone_dir.as_deref(),
+                *force,
+                config.profile(profile.clone()).as_deref(),
+                no_hooks,
+                dry_run,
+                config,
+            ),
+            Command::Restore => restore::execute(dry_run),
+            Command::Clean { directory, profile } => clean::execute(
+                directory.as_deref(),
+                config.profile(profile.clone()).as_deref(),
+                no_hooks,
+                dry_run,
+                config,
+            ),
+            Command::Status { directory } => status::execute(directory.as_deref(), config),
+            Command::Install { directory, profile } => install::execute(
+                directory.as_deref(),
+                config.profile(profile.clone()).as_deref(),
+                dry_run,
+                config,
+            ),
+            Command::Init => {
                 let pwd = std::env::var("PWD").context("PWD environment variable not set")?;
-                init::execute(pwd)
+                init::execute(pwd, dry_run)
             }
-            Self::Clone {
+            Command::Clone {
                 repository,
                 directory,
-            } => clone::execute(repository, directory),
-            Self::Sync { directory } => sync::execute(directory),
+                reference,
+            } => clone::execute(
+                repository,
+                &or_default(directory),
+                reference.as_deref(),
+                dry_run,
+                config,
+            ),
+            Command::Sync {
+                directory,
+                profile,
+                strategy,
+            } => sync::execute(
+                &or_default(directory),
+                config.profile(profile.clone()).as_deref(),
+                *strategy,
+                no_hooks,
+                dry_run,
+            ),
+            Command::Push {
+                directory,
+                message,
+                all,
+            } => push::execute(&or_default(directory), message.as_deref(), *all, dry_run),
         }
     }
 }
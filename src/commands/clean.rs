@@ -1,55 +1,275 @@
 use anyhow::ensure;
-use anyhow::Context;
-use std::fs;
+use std::{fs, path::Path};
 use tracing::{debug, debug_span};
 
-use crate::dotfiles::find_magefile;
-use crate::dotfiles::ProgramOptions;
-use crate::util::show_errors;
+use crate::commands::hooks;
+use crate::config::Config;
+use crate::dotfiles::{find_magefile, layers, HookKind, LinkMode, ProgramOptions};
+use crate::error::MageError;
+use crate::output::{self, Outcome, Record};
+use crate::plan::{Action, Plan};
+use crate::state::{Link, State};
+use crate::summary::Summary;
 use crate::util::FullPath;
 
-pub(crate) fn execute(dotfiles_path: &str) -> anyhow::Result<()> {
+/// Cleans the dotfiles in `dotfiles_path`, or the sources in the user config without one
+pub(crate) fn execute(
+    dotfiles_path: Option<&str>,
+    profile: Option<&str>,
+    no_hooks: bool,
+    dry_run: bool,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let layers = layers(dotfiles_path, None, config)?;
+    // A missing or invalid magefile fails before the state is loaded
+    for layer in &layers {
+        find_magefile(layer.path().as_ref())?;
+    }
+    let mut state = State::load()?;
+
+    if dry_run {
+        let mut all = Plan::default();
+        for layer in &layers {
+            all.extend(plan(layer.path().to_str(), profile, no_hooks, &state)?);
+        }
+        output::plan(all);
+        return Ok(());
+    }
+
+    let mut summary = Summary::default();
+    let result = layers.iter().try_for_each(|layer| -> anyhow::Result<()> {
+        if let Some(name) = &layer.name {
+            output::print(format!("Cleaning {name}:\n"));
+        }
+        summary.extend(clean(layer.path().to_str(), profile, no_hooks, &mut state)?);
+        Ok(())
+    });
+    // Links removed before a failure are gone, so they are not kept in the state
+    state.save()?;
+    result?;
+    summary.finish()
+}
+
+/// Removes the symlinks recorded in `state` that point into the dotfiles, then runs
+/// the `post_clean` hooks. Returns what was done to the links
+fn clean(
+    dotfiles_path: &str,
+    profile: Option<&str>,
+    no_hooks: bool,
+    state: &mut State,
+) -> anyhow::Result<Summary> {
+    let span = debug_span!("clean");
+    let _guard = span.enter();
+
+    let (links, programs) = selected_links(dotfiles_path, profile, no_hooks, state)?;
+    let summary = links
+        .iter()
+        .map(|link| {
+            let record = |action, e: &anyhow::Error| {
+                output::report(
+                    Record::failed(action, e)
+                        .origin(&link.origin)
+                        .target(&link.target),
+                )
+            };
+            let outcome = link
+                .undo()
+                .map_err(anyhow::Error::from)
+                .inspect_err(|e| record("clean", e))?;
+            state.remove(&link.target);
+            match program_of(link, &programs) {
+                Some(program) if outcome == Outcome::Done => program
+                    .run_hook(HookKind::PostClean)
+                    .inspect_err(|e| record("hook", e))?,
+                _ => {}
+            }
+            Ok(outcome)
+        })
+        .collect::<Summary>();
+
+    remove_empty_dirs(state)?;
+    let magefile_hooks = hooks::load(&[dotfiles_path.into()], no_hooks)?;
+    hooks::run(&magefile_hooks, HookKind::PostClean)?;
+    Ok(summary)
+}
+
+/// Removes the directories mage created for targets that are empty now
+pub(crate) fn remove_empty_dirs(state: &mut State) -> anyhow::Result<()> {
+    let dirs = state
+        .remove_empty_dirs()
+        .inspect_err(|e| output::report(Record::failed("remove_dir", e)))?;
+    for dir in dirs {
+        output::report(
+            Record::new("remove_dir", Outcome::Done)
+                .target(&dir)
+                .text(format!("{} removed ✔️", dir.display())),
+        );
+    }
+
+    Ok(())
+}
+
+/// Plans removing the symlinks recorded in `state` without touching anything
+pub(crate) fn plan(
+    dotfiles_path: &str,
+    profile: Option<&str>,
+    no_hooks: bool,
+    state: &State,
+) -> anyhow::Result<Plan> {
     let span = debug_span!("clean");
     let _guard = span.enter();
+
+    let mut plan = Plan::default();
+    let (links, programs) = selected_links(dotfiles_path, profile, no_hooks, state)?;
+    let links = links
+        .into_iter()
+        .filter(Link::is_linked)
+        .collect::<Vec<_>>();
+    let targets = links.iter().map(|l| l.target.clone()).collect::<Vec<_>>();
+
+    for link in links {
+        let hook = program_of(&link, &programs).and_then(|p| p.hook(HookKind::PostClean));
+        if let Some(hook) = hook {
+            plan.push(Action::Hook(hook.to_string()));
+        }
+        plan_unlink(link, &mut plan);
+    }
+    // Directories mage created are removed once the links in them are gone
+    for dir in state.emptied_dirs(&targets) {
+        plan.push(Action::DeleteDir(dir));
+    }
+    let magefile_hooks = hooks::load(&[dotfiles_path.into()], no_hooks)?;
+    hooks::plan(&magefile_hooks, HookKind::PostClean, &mut plan);
+
+    Ok(plan)
+}
+
+/// Plans what `unlink` removes for `link`
+pub(crate) fn plan_unlink(link: Link, plan: &mut Plan) {
+    match link.mode {
+        LinkMode::Symlink => plan.push(Action::DeleteSymlink(link.target)),
+        _ => plan.push(Action::DeleteFile(link.target)),
+    }
+    if let Some(rendered) = link.rendered.filter(|r| r.is_unchanged()) {
+        plan.push(Action::DeleteFile(rendered.path));
+    }
+}
+
+/// Removes the link mage recorded at `target`, skipped if there is none
+pub(crate) fn unlink(target: &Path, state: &mut State) -> anyhow::Result<Outcome> {
+    let Some(link) = state.link(target).cloned() else {
+        return Ok(Outcome::Skipped);
+    };
+
+    let outcome = link.undo().map_err(anyhow::Error::from).inspect_err(|e| {
+        output::report(
+            Record::failed("clean", e)
+                .origin(&link.origin)
+                .target(target),
+        );
+    })?;
+    state.remove(target);
+    Ok(outcome)
+}
+
+/// Links in `state` from the dotfiles, only the entries of `profile` if one is given.
+/// Returned with the programs of the magefile for their hooks, which are left out
+/// with `no_hooks`
+fn selected_links(
+    dotfiles_path: &str,
+    profile: Option<&str>,
+    no_hooks: bool,
+    state: &State,
+) -> anyhow::Result<(Vec<Link>, Vec<ProgramOptions>)> {
     let full_path: FullPath = dotfiles_path.into();
 
+    // Make sure this is a dotfiles directory before touching anything
     ensure!(full_path.as_ref().exists(), "invalid path: {}", full_path);
-
     let magefile = find_magefile(full_path.as_ref())?;
-    let programs = ProgramOptions::generate(magefile, full_path)?;
-    let errors = programs.iter().map(Undo::undo).collect::<Vec<_>>();
-    show_errors(errors);
 
-    Ok(())
+    // The programs are only needed to select the profile and for their hooks
+    let mut programs = match (profile, no_hooks) {
+        (None, true) => vec![],
+        _ => ProgramOptions::generate_profile(magefile, full_path.clone(), profile)?,
+    };
+    if no_hooks {
+        hooks::skip(&mut programs);
+    }
+
+    let mut links = state.links_from(full_path.as_ref());
+    if profile.is_some() {
+        links.retain(|link| program_of(link, &programs).is_some());
+    }
+
+    Ok((links, programs))
+}
+
+fn program_of<'a>(link: &Link, programs: &'a [ProgramOptions]) -> Option<&'a ProgramOptions> {
+    programs
+        .iter()
+        .find(|p| link.origin == p.origin_path.as_path())
 }
 
 trait Undo {
-    fn undo(&self) -> anyhow::Result<()>;
+    /// Returns `Done` if the target was removed, `Skipped` if it is not mage's anymore
+    fn undo(&self) -> Result<Outcome, MageError>;
 }
 
-impl Undo for ProgramOptions {
-    fn undo(&self) -> anyhow::Result<()> {
-        let span = debug_span!("program", origin = ?self.origin_path);
+impl Undo for Link {
+    fn undo(&self) -> Result<Outcome, MageError> {
+        let span = debug_span!("link", origin = ?self.origin);
         let _guard = span.enter();
 
-        let path_ref = self.target_path.as_ref();
-        // Only remove file if it is a symlink
-        if path_ref.exists() && path_ref.is_symlink() {
-            fs::remove_dir_all(self.target_path.clone())
-                .context(format!("delete symlink for: {}", self.origin_path))?;
-            debug!(symlink = ?self.target_path, "delete");
-        } else {
-            debug!(target = ?self.target_path, "not a symlink");
-            println!(
-                "{} is not a symlink or it doesn't exists, skipping ✔️",
-                self.origin_path
+        // Only remove the target if it still points to the dotfiles, copies only if
+        // they weren't changed since
+        if !self.is_linked() {
+            debug!(target = ?self.target, "not linked by mage");
+            output::report(
+                Record::new("clean", Outcome::Skipped)
+                    .origin(&self.origin)
+                    .target(&self.target)
+                    .detail("no longer linked by mage")
+                    .text(format!(
+                        "{} is no longer linked by mage, skipping ✔️",
+                        self.target.display()
+                    )),
             );
-            return Ok(());
+            return Ok(Outcome::Skipped);
         }
 
-        println!("{} cleaned ✔️", self.origin_path);
+        fs::remove_file(&self.target).map_err(|e| {
+            MageError::io(
+                e,
+                &self.target,
+                format!("delete link for: {}", self.origin.display()),
+            )
+        })?;
+        debug!(target = ?self.target, mode = ?self.mode, "delete");
+
+        let mut record = Record::new("clean", Outcome::Done)
+            .origin(&self.origin)
+            .target(&self.target);
+        // Keep rendered templates that were edited by hand
+        if let Some(rendered) = &self.rendered {
+            if rendered.is_unchanged() {
+                fs::remove_file(&rendered.path).map_err(|e| {
+                    MageError::io(
+                        e,
+                        &rendered.path,
+                        format!("delete rendered template {}", rendered.path.display()),
+                    )
+                })?;
+                debug!(rendered = ?rendered.path, "delete");
+            } else if rendered.path.exists() {
+                let kept = format!("{} was edited by hand, keeping it", rendered.path.display());
+                output::print(format!("{kept}\n"));
+                record = record.detail(kept);
+            }
+        }
+
+        output::report(record.text(format!("{} cleaned ✔️", self.origin.display())));
         debug!("done");
-        Ok(())
+        Ok(Outcome::Done)
     }
 }
 
@@ -59,43 +279,216 @@ mod tests {
     use std::{os::unix::fs::symlink, path::PathBuf};
 
     use super::*;
+    use crate::state::Rendered;
+    use crate::util::hash;
 
-    fn setup() -> PathBuf {
+    fn setup(target: &str) -> (PathBuf, Link) {
         let original = PathBuf::from("examples/test-dotfiles/example.config")
             .canonicalize()
             .expect("should be able to canonicalize");
-        let target = PathBuf::from("/tmp/example.config");
+        let target = PathBuf::from(target);
         if target.exists() {
             fs::remove_dir_all(&target).expect("should be able to remove this dir");
         }
 
-        symlink(original, target).expect("should be able to create this symlink");
+        symlink(&original, &target).expect("should be able to create this symlink");
 
-        PathBuf::from("examples/test-dotfiles")
+        let dotfiles_path = PathBuf::from("examples/test-dotfiles")
             .canonicalize()
-            .unwrap()
+            .unwrap();
+        let link = Link::new(&original.into(), &target.into());
+        (dotfiles_path, link)
     }
 
     #[test]
     fn test_clean_cmd() {
-        let dotfiles_path = setup();
-        let _res = execute(dotfiles_path.to_str().unwrap()).unwrap();
+        let (dotfiles_path, link) = setup("/tmp/example.config");
+        let mut state = State::default();
+        state.insert(link);
+
+        clean(dotfiles_path.to_str().unwrap(), None, false, &mut state).unwrap();
 
         let target_path = PathBuf::from("/tmp/example.config");
-        assert!(!target_path.exists());
+        assert!(!target_path.is_symlink());
+        assert!(state.links_from(&dotfiles_path).is_empty());
+    }
+
+    #[test]
+    fn does_not_clean_unrecorded_symlinks() {
+        let (dotfiles_path, _) = setup("/tmp/mage-hand-made.config");
+        let mut state = State::default();
+
+        clean(dotfiles_path.to_str().unwrap(), None, false, &mut state).unwrap();
+
+        let target_path = PathBuf::from("/tmp/mage-hand-made.config");
+        assert!(target_path.is_symlink());
+        fs::remove_file(target_path).unwrap();
+    }
+
+    #[test]
+    fn does_not_clean_outside_profile() {
+        let (dotfiles_path, link) = setup("/tmp/mage-profile.config");
+        let mut state = State::default();
+        state.insert(link);
+
+        let result = clean(
+            dotfiles_path.to_str().unwrap(),
+            Some("missing"),
+            false,
+            &mut state,
+        );
+
+        let target_path = PathBuf::from("/tmp/mage-profile.config");
+        assert!(result.is_err());
+        assert!(target_path.is_symlink());
+        fs::remove_file(target_path).unwrap();
+    }
+
+    #[test]
+    fn plan_does_not_clean() {
+        let (dotfiles_path, link) = setup("/tmp/mage-plan.config");
+        let mut state = State::default();
+        state.insert(link);
+
+        let plan = plan(dotfiles_path.to_str().unwrap(), None, false, &state).unwrap();
+
+        let target_path = PathBuf::from("/tmp/mage-plan.config");
+        assert!(target_path.is_symlink());
+        assert_eq!(
+            plan.actions,
+            vec![Action::DeleteSymlink(target_path.clone())]
+        );
+        fs::remove_file(target_path).unwrap();
+    }
+
+    #[test]
+    fn cleans_rendered_templates() {
+        let (dotfiles_path, mut link) = setup("/tmp/mage-rendered.config");
+        let rendered = PathBuf::from("/tmp/mage-rendered.config.rendered");
+        fs::write(&rendered, "rendered").unwrap();
+        fs::remove_file(&link.target).unwrap();
+        symlink(&rendered, &link.target).unwrap();
+        link.rendered = Some(Rendered {
+            path: rendered.clone(),
+            hash: hash("rendered"),
+        });
+        let mut state = State::default();
+        state.insert(link);
+
+        clean(dotfiles_path.to_str().unwrap(), None, false, &mut state).unwrap();
+
+        assert!(!rendered.exists());
+    }
+
+    #[test]
+    fn keeps_edited_copies() {
+        let (dotfiles_path, mut link) = setup("/tmp/mage-copy.config");
+        let target = link.target.clone();
+        fs::remove_file(&target).unwrap();
+        fs::write(&target, "copied").unwrap();
+        link.mode = LinkMode::Copy;
+        link.hash = Some(hash("copied"));
+        let mut state = State::default();
+        state.insert(link.clone());
+
+        assert_eq!(
+            plan(dotfiles_path.to_str().unwrap(), None, false, &state)
+                .unwrap()
+                .actions,
+            vec![Action::DeleteFile(target.clone())]
+        );
+
+        fs::write(&target, "edited").unwrap();
+        clean(dotfiles_path.to_str().unwrap(), None, false, &mut state).unwrap();
+        assert!(target.exists());
+
+        fs::write(&target, "copied").unwrap();
+        state.insert(link);
+        clean(dotfiles_path.to_str().unwrap(), None, false, &mut state).unwrap();
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn removes_created_dirs() {
+        let root = PathBuf::from("/tmp/mage-clean-dirs");
+        fs::remove_dir_all(&root).unwrap_or_default();
+        fs::create_dir_all(root.join("nvim/lua")).unwrap();
+        let (dotfiles_path, mut link) = setup("/tmp/mage-clean-dirs/nvim/lua/example.config");
+        link.created_dirs = vec![root.join("nvim"), root.join("nvim/lua")];
+        let mut state = State::default();
+        state.insert(link);
+
+        assert_eq!(
+            plan(dotfiles_path.to_str().unwrap(), None, false, &state)
+                .unwrap()
+                .actions[1..],
+            [
+                Action::DeleteDir(root.join("nvim/lua")),
+                Action::DeleteDir(root.join("nvim"))
+            ]
+        );
+
+        clean(dotfiles_path.to_str().unwrap(), None, false, &mut state).unwrap();
+        assert!(!root.join("nvim").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn runs_post_clean_hooks() {
+        let root = PathBuf::from("/tmp/mage-clean-hooks");
+        let dotfiles = root.join("dotfiles");
+        fs::remove_dir_all(&root).unwrap_or_default();
+        fs::create_dir_all(&dotfiles).unwrap();
+        fs::write(dotfiles.join("a"), "a").unwrap();
+        fs::write(
+            dotfiles.join("magefile"),
+            r#"
+            [hooks]
+            post_clean = "touch ../all-cleaned"
+
+            ["a"]
+            target_path = "/tmp/mage-clean-hooks/a.link"
+            post_clean = "touch ../a-cleaned"
+            "#,
+        )
+        .unwrap();
+        let link_a = |state: &mut State| {
+            symlink(dotfiles.join("a"), root.join("a.link")).unwrap();
+            state.insert(Link::new(
+                &dotfiles.join("a").into(),
+                &root.join("a.link").into(),
+            ));
+        };
+        let mut state = State::default();
+        let dotfiles_path = dotfiles.to_str().unwrap();
+
+        link_a(&mut state);
+        clean(dotfiles_path, None, true, &mut state).unwrap();
+        assert!(!root.join("a-cleaned").exists());
+
+        link_a(&mut state);
+        let plan = plan(dotfiles_path, None, false, &state).unwrap();
+        assert_eq!(plan.actions.len(), 3);
+        clean(dotfiles_path, None, false, &mut state).unwrap();
+        assert!(root.join("a-cleaned").exists());
+        assert!(root.join("all-cleaned").exists());
+        fs::remove_dir_all(root).unwrap();
     }
 
     #[test]
     #[should_panic]
     fn invalid_path() {
         let invalid_path = "asdfsdf";
-        execute(invalid_path).unwrap()
+        execute(Some(invalid_path), None, false, false, &Config::default()).unwrap()
     }
 
     #[test]
     fn no_magefile() {
         let invalid_path = "/tmp";
-        let err = execute(invalid_path).unwrap_err().to_string();
-        assert_eq!(err, "Magefile not found");
+        let err = execute(Some(invalid_path), None, false, false, &Config::default()).unwrap_err();
+        assert!(matches!(
+            crate::error::find(&err),
+            Some(MageError::MagefileNotFound { dir }) if dir == Path::new(invalid_path)
+        ));
     }
 }
@@ -1,18 +1,42 @@
 use anyhow::anyhow;
 
 use crate::{
+    config::Config,
     dotfiles::{clone_repo, DotfilesOrigin},
+    output,
+    plan::{Action, Plan},
     util::get_full_path,
 };
 
-pub(crate) fn execute(repository: &str, directory: &str) -> Result<(), anyhow::Error> {
+/// Clones `repository`, `reference` takes precedence over a `#ref` in the repository
+pub(crate) fn execute(
+    repository: &str,
+    directory: &str,
+    reference: Option<&str>,
+    dry_run: bool,
+    config: &Config,
+) -> Result<(), anyhow::Error> {
     let full_dir_path = get_full_path(directory)
         .to_str()
         .expect("should be able to convert back to str")
         .to_string();
 
-    match repository.parse()? {
-        DotfilesOrigin::Repository(repo, _) => clone_repo(&repo, &full_dir_path).map(|_| ()),
+    match DotfilesOrigin::parse(repository, &config.repo_defaults())? {
+        DotfilesOrigin::Repository(repo, _, parsed) => {
+            let reference = reference.or(parsed.as_deref());
+            if !dry_run {
+                return clone_repo(&repo, &full_dir_path, reference).map(|_| ());
+            }
+
+            let mut plan = Plan::default();
+            let args = ["clone", "--recurse-submodules", &repo, &full_dir_path];
+            plan.push(Action::git(&args));
+            if let Some(reference) = reference {
+                plan.push(Action::git(&["-C", &full_dir_path, "checkout", reference]));
+            }
+            output::plan(plan);
+            Ok(())
+        }
         _ => Err(anyhow!("Invalid repository: {repository}")),
     }
 }
@@ -32,7 +56,7 @@ mod test {
         ctx.set_target_dir(PathBuf::from("/tmp/test"));
         let repo = "https://github.com/ollivarila/brainfckr";
         let dir = "/tmp/test";
-        execute(repo, dir).unwrap();
+        execute(repo, dir, None, false, &Config::default()).unwrap();
         assert!(PathBuf::from(dir).exists())
     }
 
@@ -40,7 +64,7 @@ mod test {
     fn invalid_url() {
         let repo = "invalid";
         let dir = "/tmp/test";
-        let result = execute(repo, dir);
+        let result = execute(repo, dir, None, false, &Config::default());
 
         assert!(result.is_err())
     }
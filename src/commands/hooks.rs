@@ -0,0 +1,49 @@
+use anyhow::Result;
+
+use crate::dotfiles::{magefile_hooks, Hook, HookKind, ProgramOptions};
+use crate::output::{self, Outcome, Record};
+use crate::plan::{Action, Plan};
+use crate::util::FullPath;
+
+/// Hooks of the magefiles in `dirs`, none with `no_hooks`. Directories that don't
+/// exist yet are skipped, a dry run has not cloned them
+pub fn load(dirs: &[FullPath], no_hooks: bool) -> Result<Vec<Hook>> {
+    if no_hooks {
+        return Ok(vec![]);
+    }
+
+    let mut hooks = vec![];
+    for dir in dirs.iter().filter(|dir| dir.as_path().exists()) {
+        hooks.extend(magefile_hooks(dir.as_path())?);
+    }
+    Ok(hooks)
+}
+
+/// Removes the hooks of `programs` for `--no-hooks`
+pub fn skip(programs: &mut [ProgramOptions]) {
+    programs
+        .iter_mut()
+        .for_each(|program| program.hooks.clear());
+}
+
+/// Runs the `kind` hooks, stops at the first one that fails
+pub fn run(hooks: &[Hook], kind: HookKind) -> Result<()> {
+    for hook in hooks.iter().filter(|hook| hook.kind == kind) {
+        hook.run()?;
+        output::report(
+            Record::new("hook", Outcome::Done)
+                .detail(hook)
+                .text(format!("{hook} ran ✔️")),
+        );
+    }
+
+    Ok(())
+}
+
+/// Plans running the `kind` hooks
+pub fn plan(hooks: &[Hook], kind: HookKind, plan: &mut Plan) {
+    hooks
+        .iter()
+        .filter(|hook| hook.kind == kind)
+        .for_each(|hook| plan.push(Action::Hook(hook.to_string())));
+}
@@ -1,9 +1,19 @@
 use anyhow::Context;
 use std::{fs::File, io::Write, path::PathBuf};
 
-pub(crate) fn execute(path: impl Into<PathBuf>) -> anyhow::Result<()> {
+use crate::output;
+use crate::plan::{Action, Plan};
+
+pub(crate) fn execute(path: impl Into<PathBuf>, dry_run: bool) -> anyhow::Result<()> {
     let mut path: PathBuf = path.into();
     path.push("magefile.toml");
+
+    if dry_run {
+        let mut plan = Plan::default();
+        plan.push(Action::CreateFile(path));
+        output::plan(plan);
+        return Ok(());
+    }
     let mut magefile = Magefile {
         file: File::create(path).context("create magefile")?,
     };
@@ -41,7 +51,7 @@ mod tests {
 
     #[test]
     fn test_init_cmd() {
-        let res = execute("/tmp");
+        let res = execute("/tmp", false);
 
         assert!(res.is_ok());
 
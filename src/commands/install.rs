@@ -0,0 +1,166 @@
+use anyhow::{ensure, Context, Result};
+use tracing::debug_span;
+
+use crate::commands::link;
+use crate::config::Config;
+use crate::dotfiles::{layers, magefile_packages, Layer, Package};
+use crate::output::{self, Outcome, Record};
+use crate::packages::{detect, PackageManager};
+use crate::plan::{Action, Plan};
+
+/// Installs the packages the dotfiles in `dotfiles_path` need, or those of the sources
+/// in the user config without one
+pub(crate) fn execute(
+    dotfiles_path: Option<&str>,
+    profile: Option<&str>,
+    dry_run: bool,
+    config: &Config,
+) -> Result<()> {
+    let span = debug_span!("install");
+    let _guard = span.enter();
+
+    let layers = layers(dotfiles_path, None, config)?;
+    let packages = required(&layers, profile)?;
+    let manager = detect().context(
+        "No supported package manager found, mage can use brew, apt, dnf, pacman or cargo",
+    )?;
+
+    if dry_run {
+        output::plan(plan(manager.as_ref(), &packages));
+        return Ok(());
+    }
+    install(manager.as_ref(), &packages)
+}
+
+/// Packages of the magefiles and of the entries that are linked on this machine
+pub(crate) fn required(layers: &[Layer], profile: Option<&str>) -> Result<Vec<Package>> {
+    let mut packages: Vec<Package> = vec![];
+    for layer in layers {
+        let full_path = layer.path();
+        ensure!(full_path.as_ref().exists(), "invalid path: {}", full_path);
+
+        let declared = magefile_packages(full_path.as_path())?;
+        let programs = link::programs(full_path.clone(), profile)?;
+        let required = programs.into_iter().flat_map(|program| program.requires);
+        for package in declared.into_iter().chain(required) {
+            if !packages.iter().any(|p| p.name == package.name) {
+                packages.push(package);
+            }
+        }
+    }
+
+    Ok(packages)
+}
+
+/// Names of the packages that are not installed, for `manager`
+fn missing(manager: &dyn PackageManager, packages: &[Package]) -> Vec<String> {
+    packages
+        .iter()
+        .filter(|package| !package.is_installed())
+        .map(|package| package.name_for(manager.name()).to_string())
+        .collect()
+}
+
+fn install(manager: &dyn PackageManager, packages: &[Package]) -> Result<()> {
+    let missing = missing(manager, packages);
+    if missing.is_empty() {
+        output::report(
+            Record::new("install", Outcome::Unchanged).text("All packages are installed ✔️"),
+        );
+        return Ok(());
+    }
+
+    manager.install(&missing)?;
+    output::report(
+        Record::new("install", Outcome::Done)
+            .detail(missing.join(", "))
+            .text(format!("{} installed ✔️", missing.join(", "))),
+    );
+    Ok(())
+}
+
+fn plan(manager: &dyn PackageManager, packages: &[Package]) -> Plan {
+    let mut plan = Plan::default();
+    let missing = missing(manager, packages);
+    if !missing.is_empty() {
+        plan.push(Action::Install {
+            manager: manager.name().to_string(),
+            packages: missing,
+        });
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, fs, path::PathBuf};
+
+    use super::*;
+
+    /// Records the packages instead of installing them
+    #[derive(Default)]
+    struct MockManager {
+        installed: RefCell<Vec<String>>,
+    }
+
+    impl PackageManager for MockManager {
+        fn name(&self) -> &'static str {
+            "apt"
+        }
+
+        fn install(&self, packages: &[String]) -> Result<()> {
+            self.installed.borrow_mut().extend_from_slice(packages);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn installs_missing_packages() {
+        let dir = PathBuf::from("/tmp/mage-install");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("magefile"),
+            r#"
+            [packages.mage-tools]
+            apt = "mage-tools-apt"
+
+            [packages.mage-editor]
+            bin = "mage-edit"
+
+            ["nvim"]
+            target_path = "/tmp/mage-install/nvim.link"
+            requires = ["mage-editor", "sh"]
+
+            ["other"]
+            target_path = "/tmp/mage-install/other.link"
+            requires = "mage-other"
+            os = "not-an-os"
+            "#,
+        )
+        .unwrap();
+        let layers = [Layer {
+            name: None,
+            origin: crate::dotfiles::DotfilesOrigin::Directory(dir.clone().into()),
+        }];
+
+        let packages = required(&layers, None).unwrap();
+        let names = packages.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["mage-editor", "mage-tools", "sh"]);
+
+        let manager = MockManager::default();
+        assert_eq!(
+            plan(&manager, &packages).actions,
+            vec![Action::Install {
+                manager: "apt".into(),
+                packages: vec!["mage-editor".into(), "mage-tools-apt".into()]
+            }]
+        );
+        install(&manager, &packages).unwrap();
+        assert_eq!(
+            *manager.installed.borrow(),
+            vec!["mage-editor", "mage-tools-apt"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,15 +1,131 @@
+mod backup;
 mod configure;
 mod init;
+use anyhow::Result;
+use backup::{backup_conflicts, backup_dir};
 use configure::configure;
+pub(crate) use init::programs;
 use tracing::debug_span;
 
-use crate::util::show_errors;
+use crate::{
+    config::Config,
+    dotfiles::{layers, merge, Conflict, HookKind, Layer, ProgramOptions},
+    output::{self, Outcome, Record},
+    plan::Plan,
+    state::State,
+    summary::Summary,
+    util::FullPath,
+};
+
+use super::hooks;
+
+/// Links the dotfiles in `directory`, a repository is cloned to `clone_dir` or the
+/// default location first. Without a directory the sources in the user config are
+/// linked together
+pub fn execute(
+    directory: Option<&str>,
+    clone_dir: Option<&str>,
+    force: bool,
+    profile: Option<&str>,
+    no_hooks: bool,
+    dry_run: bool,
+    config: &Config,
+) -> Result<()> {
+    let layers = layers(directory, clone_dir, config)?;
+    if dry_run {
+        let state = State::load()?;
+        output::plan(plan(&layers, force, profile, no_hooks, &state)?);
+        return Ok(());
+    }
 
-pub fn execute(directory: &str) -> anyhow::Result<()> {
     debug_span!("link").in_scope(|| {
-        let programs = init::run(directory)?;
-        let result = configure(programs);
-        show_errors(result);
-        Ok(())
+        let programs = layers
+            .iter()
+            .map(|layer| init::run(layer.origin.clone(), profile))
+            .collect::<Result<Vec<_>>>()?;
+        let (programs, conflicts) = merge(&layers, programs);
+        let mut programs: Vec<_> = programs.into_iter().map(|(_, program)| program).collect();
+        let magefile_hooks = hooks::load(&paths(&layers), no_hooks)?;
+        if no_hooks {
+            hooks::skip(&mut programs);
+        }
+        hooks::run(&magefile_hooks, HookKind::PreLink)?;
+        let mut state = State::load()?;
+
+        let mut summary = Summary::default();
+        if force {
+            summary = backup_conflicts(&programs, &backup_dir(), &mut state);
+            state.save()?;
+        }
+
+        summary.extend(link(programs, &mut state));
+        state.save()?;
+        report(&conflicts);
+        hooks::run(&magefile_hooks, HookKind::PostLink)?;
+        summary.finish()
+    })
+}
+
+fn paths(layers: &[Layer]) -> Vec<FullPath> {
+    layers.iter().map(|layer| layer.path().clone()).collect()
+}
+
+/// Prints the targets several sources link
+fn report(conflicts: &[Conflict]) {
+    if conflicts.is_empty() {
+        return;
+    }
+
+    output::print("Conflicting entries, the source with the higher priority is linked:\n");
+    for conflict in conflicts {
+        output::report(
+            Record::new("link", Outcome::Skipped)
+                .target(&conflict.target)
+                .detail(conflict)
+                .text(format!("  {conflict}")),
+        );
+    }
+}
+
+/// Links `programs` and records the created links in `state`
+pub(crate) fn link(programs: Vec<ProgramOptions>, state: &mut State) -> Summary {
+    let (summary, links) = configure(programs, state);
+    links.into_iter().for_each(|link| state.insert(link));
+    summary
+}
+
+/// Plans linking without touching anything
+pub fn plan(
+    layers: &[Layer],
+    force: bool,
+    profile: Option<&str>,
+    no_hooks: bool,
+    state: &State,
+) -> Result<Plan> {
+    debug_span!("link").in_scope(|| {
+        let mut plan = Plan::default();
+        let programs = layers
+            .iter()
+            .map(|layer| init::plan(layer.origin.clone(), profile, &mut plan))
+            .collect::<Result<Vec<_>>>()?;
+        let (programs, conflicts) = merge(layers, programs);
+        report(&conflicts);
+        let backup_dir = force.then(backup_dir);
+        let magefile_hooks = hooks::load(&paths(layers), no_hooks)?;
+        hooks::plan(&magefile_hooks, HookKind::PreLink, &mut plan);
+
+        for (_, mut program) in programs {
+            if no_hooks {
+                program.hooks.clear();
+            }
+            let status = program.recorded_status(state);
+            match configure::plan(&program, status, backup_dir.as_deref()) {
+                Ok(actions) => actions.into_iter().for_each(|a| plan.push(a)),
+                Err(e) => plan.errors.push(e),
+            }
+        }
+
+        hooks::plan(&magefile_hooks, HookKind::PostLink, &mut plan);
+        Ok(plan)
     })
 }
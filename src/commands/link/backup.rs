@@ -0,0 +1,124 @@
+use anyhow::{ensure, Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, debug_span};
+
+use crate::{
+    dotfiles::{LinkStatus, ProgramOptions},
+    output::{self, Outcome, Record},
+    state::{now, Backup, State},
+    summary::Summary,
+    util::{data_dir, mirror_path, move_path, FullPath},
+};
+
+/// Directory for the backups of this run, e.g. `~/.local/share/mage/backups/<timestamp>`
+pub fn backup_dir() -> PathBuf {
+    let mut path = data_dir();
+    path.push("backups");
+    path.push(now().to_string());
+    path
+}
+
+/// Moves every file that is in the way of a symlink into `backup_dir`
+pub fn backup_conflicts(
+    programs: &[ProgramOptions],
+    backup_dir: &Path,
+    state: &mut State,
+) -> Summary {
+    let span = debug_span!("backup");
+    let _guard = span.enter();
+
+    let conflicts = programs
+        .iter()
+        .filter(|program| {
+            matches!(
+                program.recorded_status(state),
+                LinkStatus::Blocked | LinkStatus::WrongTarget(_) | LinkStatus::Dangling(_)
+            )
+        })
+        .collect::<Vec<_>>();
+
+    conflicts
+        .into_iter()
+        .map(|program| {
+            let backup = backup_target(&program.target_path, backup_dir).inspect_err(|e| {
+                output::report(Record::failed("backup", e).program(program));
+            })?;
+            output::report(
+                Record::new("backup", Outcome::Done)
+                    .program(program)
+                    .detail(backup.backup.display())
+                    .text(format!(
+                        "{} backed up to {} ✔️",
+                        program.target_path,
+                        backup.backup.display()
+                    )),
+            );
+            state.insert_backup(backup);
+            Ok(Outcome::Done)
+        })
+        .collect()
+}
+
+/// Location of the backup of `target`, the full target path is mirrored inside `backup_dir`
+pub fn backup_path(target: &Path, backup_dir: &Path) -> PathBuf {
+    mirror_path(target, backup_dir)
+}
+
+fn backup_target(target: &FullPath, backup_dir: &Path) -> Result<Backup> {
+    let backup_path = backup_path(target.as_path(), backup_dir);
+
+    // Two backups in the same second share a directory
+    ensure!(
+        fs::symlink_metadata(&backup_path).is_err(),
+        "{} already exists, not backing up {target}",
+        backup_path.display()
+    );
+    let parent = backup_path.parent().context("get parent path")?;
+    fs::create_dir_all(parent).context("create backup directory")?;
+    move_path(target.as_path(), &backup_path).context(format!("move {target} to backup"))?;
+    debug!(target = ?target, backup = ?backup_path, "moved");
+
+    Ok(Backup {
+        target: target.as_path().to_path_buf(),
+        backup: backup_path,
+        created_at: now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_context::Ctx;
+
+    #[test]
+    fn backs_up_blocked_target() {
+        let mut ctx = Ctx::default();
+        let backup_dir = PathBuf::from(format!("{}.backup", ctx.opts.target_path));
+        ctx.set_target_dir(backup_dir.clone());
+        fs::write(&ctx.opts.target_path, "distro config").unwrap();
+
+        let mut state = State::default();
+        let summary = backup_conflicts(std::slice::from_ref(&ctx.opts), &backup_dir, &mut state);
+
+        assert_eq!((summary.succeeded, summary.failed), (1, 0));
+        assert_eq!(ctx.opts.link_status(), LinkStatus::Missing);
+        let backup = &state.backups()[0];
+        assert_eq!(fs::read_to_string(&backup.backup).unwrap(), "distro config");
+    }
+
+    #[test]
+    fn does_not_back_up_missing_target() {
+        let ctx = Ctx::default();
+        let backup_dir = PathBuf::from("/tmp/mage-unused-backup");
+
+        let mut state = State::default();
+        let summary = backup_conflicts(std::slice::from_ref(&ctx.opts), &backup_dir, &mut state);
+
+        assert_eq!(summary.total(), 0);
+        assert!(state.backups().is_empty());
+        assert!(!backup_dir.exists());
+    }
+}
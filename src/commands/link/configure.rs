@@ -1,82 +1,586 @@
-use crate::dotfiles::ProgramOptions;
+use super::backup::backup_path;
+use crate::dotfiles::{HookKind, LinkMode, LinkStatus, ProgramOptions, Template};
+use crate::error::MageError;
+use crate::output::{self, Outcome, Record};
+use crate::plan::Action;
+use crate::state::{Link, Rendered, State};
+use crate::summary::Summary;
+use crate::util::hash;
 use anyhow::{ensure, Context, Result};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use std::{fs, os::unix::fs::symlink, path::Path};
+use std::{
+    fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
 use tracing::{debug, debug_span};
 
 pub trait Configure {
-    fn configure(&self) -> Result<()>;
+    /// Returns what was done, with the created or updated link if there is one to record
+    fn configure(&self, state: &State) -> Result<(Outcome, Option<Link>), MageError>;
 }
 
 impl Configure for ProgramOptions {
-    fn configure(&self) -> Result<()> {
-        // Ensure the origin path exists
-        ensure!(
-            self.origin_path.as_ref().exists(),
-            "{} does not exist",
-            self.origin_path
+    fn configure(&self, state: &State) -> Result<(Outcome, Option<Link>), MageError> {
+        let target = self.target_path.as_path();
+        let rendered = match (&self.template, self.recorded_status(state)) {
+            (Some(template), LinkStatus::Missing | LinkStatus::Linked | LinkStatus::Outdated) => {
+                Some(write_rendered(self, template, state)?)
+            }
+            _ => None,
+        };
+
+        // Rendering may have outdated a copy of the template
+        let mut outdated = None;
+        match self.recorded_status(state) {
+            LinkStatus::Missing => {}
+            LinkStatus::Outdated => {
+                // Only copies mage made that nobody changed since are outdated, it's kept
+                // in memory to put it back if linking fails
+                let copy = fs::read(target)
+                    .and_then(|content| Ok((content, fs::metadata(target)?.permissions())))
+                    .map_err(|e| MageError::io(e, target, "read outdated copy"))?;
+                fs::remove_file(target)
+                    .map_err(|e| MageError::io(e, target, "delete outdated copy"))?;
+                outdated = Some(copy);
+            }
+            LinkStatus::Linked => {
+                debug!(target = ?self.target_path, "exists");
+                output::report(
+                    Record::new("link", Outcome::Unchanged)
+                        .program(self)
+                        .text(format!("{} already linked ✔️", self.origin_path)),
+                );
+
+                // Record the new hash if the rendered template changed
+                let link = rendered
+                    .filter(|(_, changed)| *changed)
+                    .map(|(rendered, _)| {
+                        let mut link = Link::new(&self.origin_path, &self.target_path);
+                        link.rendered = Some(rendered);
+                        link
+                    });
+                return Ok((Outcome::Unchanged, link));
+            }
+            LinkStatus::Skipped(reason) => {
+                debug!(reason, "skip");
+                output::report(
+                    Record::new("link", Outcome::Skipped)
+                        .program(self)
+                        .detail(reason),
+                );
+                return Ok((Outcome::Skipped, None));
+            }
+            status => return Err(not_linkable(self, status)),
+        }
+
+        let mut link = Link::new(&self.origin_path, &self.target_path);
+        if let Err(e) = create_link(self, &mut link) {
+            undo(target, &link.created_dirs, outdated);
+            return Err(e);
+        }
+
+        output::report(
+            Record::new("link", Outcome::Done)
+                .program(self)
+                .text(format!("{} linked ✔️", self.origin_path)),
         );
+        link.mode = self.mode;
+        link.rendered = rendered.map(|(rendered, _)| rendered);
+        Ok((Outcome::Done, Some(link)))
+    }
+}
+
+/// Links the target with the hooks around it, `link` gets the directories created
+/// for it. A failed post_link hook fails the entry, so the link is not kept
+fn create_link(program: &ProgramOptions, link: &mut Link) -> Result<(), MageError> {
+    let target = program.target_path.as_path();
+    ensure_linkable(program)?;
+    program.run_hook(HookKind::PreLink)?;
+
+    link.created_dirs = create_parents(target)?;
+
+    let source = program.source_path();
+    match program.mode {
+        LinkMode::Symlink => {
+            symlink(source, target).map_err(|e| MageError::io(e, target, "create symlink"))?
+        }
+        LinkMode::Hardlink => fs::hard_link(source, target)
+            .map_err(|e| MageError::io(e, target, "create hard link"))?,
+        LinkMode::Copy => {
+            let content = fs::read(source)
+                .map_err(|e| MageError::io(e, source, format!("read {}", source.display())))?;
+            fs::write(target, &content)
+                .and_then(|()| fs::set_permissions(target, fs::metadata(source)?.permissions()))
+                .map_err(|e| {
+                    fs::remove_file(target).unwrap_or_default();
+                    MageError::io(e, target, "copy to target")
+                })?;
+            link.hash = Some(hash(content));
+        }
+    }
+
+    debug!(origin = ?source, target = ?program.target_path, mode = ?program.mode, "link");
+
+    if let Err(e) = program.run_hook(HookKind::PostLink) {
+        fs::remove_file(target)
+            .map_err(|e| MageError::io(e, target, "delete link of failed entry"))?;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Removes the directories created for a failed link, deepest first, and puts back
+/// the outdated copy it replaced. Only logs what can't be undone
+fn undo(target: &Path, created_dirs: &[PathBuf], outdated: Option<(Vec<u8>, fs::Permissions)>) {
+    for dir in created_dirs {
+        if let Err(e) = fs::remove_dir(dir) {
+            debug!(?dir, %e, "failed to remove created directory");
+        }
+    }
+
+    if let Some((content, permissions)) = outdated {
+        if let Err(e) =
+            fs::write(target, content).and_then(|()| fs::set_permissions(target, permissions))
+        {
+            debug!(?target, %e, "failed to put back outdated copy");
+        }
+    }
+}
+
+/// Directories can only be symlinked
+fn ensure_linkable(program: &ProgramOptions) -> Result<()> {
+    ensure!(
+        program.mode.is_symlink() || !program.source_path().is_dir(),
+        "{} is a directory, it can't be linked in {:?} mode",
+        program.origin_path,
+        program.mode
+    );
+    Ok(())
+}
+
+/// Writes the rendered template, refusing to overwrite changes made by hand.
+/// Returns the written file and whether its content changed.
+fn write_rendered(
+    program: &ProgramOptions,
+    template: &Template,
+    state: &State,
+) -> Result<(Rendered, bool)> {
+    let content = template.render(program.origin_path.as_path())?;
+    let rendered = Rendered {
+        path: template.output.clone(),
+        hash: hash(&content),
+    };
 
-        // Check if the config file already exists
-        if self.target_path.as_ref().exists() {
-            debug!(target = ?self.target_path, "exists");
-            println!("{} already linked ✔️", self.origin_path);
-            return Ok(());
+    if let Ok(current) = fs::read(&template.output) {
+        let current = hash(current);
+        if current == rendered.hash {
+            return Ok((rendered, false));
         }
 
-        // Check if the path to the config file exists
-        ensure_path_ok(&self.target_path.as_ref())?;
+        let recorded = state
+            .link(program.target_path.as_path())
+            .and_then(|link| link.rendered.as_ref())
+            .map(|rendered| &rendered.hash);
+        ensure!(
+            recorded == Some(&current),
+            "{} was edited by hand, not overwriting it",
+            template.output.display()
+        );
+    }
+
+    ensure_path_ok(&template.output)?;
+    fs::write(&template.output, content)
+        .map_err(|e| MageError::io(e, &template.output, "write rendered template"))?;
+    debug!(output = ?template.output, "rendered");
+
+    Ok((rendered, true))
+}
+
+/// Error for a program whose target can't be linked
+fn not_linkable(program: &ProgramOptions, status: LinkStatus) -> MageError {
+    let origin = program.origin_path.as_path().to_path_buf();
+    match status {
+        LinkStatus::OriginMissing => MageError::MissingOrigin { origin },
+        status => MageError::TargetConflict {
+            target: program.target_path.as_path().to_path_buf(),
+            origin,
+            status: status.to_string(),
+        },
+    }
+}
+
+/// Actions `configure` would take for `program` if its target is in `status`.
+/// Conflicting targets are planned to be moved into `backup_dir` if it is given.
+pub fn plan(
+    program: &ProgramOptions,
+    status: LinkStatus,
+    backup_dir: Option<&Path>,
+) -> Result<Vec<Action>> {
+    let target = program.target_path.as_path();
+    let linked = status == LinkStatus::Linked;
+    let mut actions = vec![];
+
+    match (status, backup_dir) {
+        (LinkStatus::Skipped(_), _) => return Ok(actions),
+        (LinkStatus::Linked, _) => {}
+        (LinkStatus::Outdated, _) => actions.push(Action::DeleteFile(target.to_path_buf())),
+        (LinkStatus::Missing, _) => {
+            let parent = target.parent().context("get parent path")?;
+            if !parent.exists() {
+                actions.push(Action::CreateDir(parent.to_path_buf()));
+            }
+        }
+        (
+            LinkStatus::Blocked | LinkStatus::WrongTarget(_) | LinkStatus::Dangling(_),
+            Some(backup_dir),
+        ) => actions.push(Action::Backup {
+            target: target.to_path_buf(),
+            backup: backup_path(target, backup_dir),
+        }),
+        (status, _) => return Err(not_linkable(program, status).into()),
+    }
 
-        // Create symlink from dotfiles to target path
-        symlink(&self.origin_path, &self.target_path)?;
+    if let Some(template) = &program.template {
+        let content = template.render(program.origin_path.as_path())?;
+        if fs::read_to_string(&template.output).ok() != Some(content) {
+            actions.push(Action::Render {
+                origin: program.origin_path.as_path().to_path_buf(),
+                output: template.output.clone(),
+            });
+        }
+    }
 
-        debug!(origin = ?self.origin_path, target = ?self.target_path, "symlink");
+    ensure_linkable(program)?;
+    if linked {
+        return Ok(actions);
+    }
 
-        println!("{} linked ✔️", self.origin_path);
-        Ok(())
+    let hook = |kind| {
+        program
+            .hook(kind)
+            .map(|hook| Action::Hook(hook.to_string()))
+    };
+    actions.extend(hook(HookKind::PreLink));
+    let origin = program.source_path().to_path_buf();
+    let target = target.to_path_buf();
+    match program.mode {
+        LinkMode::Symlink => actions.push(Action::Symlink { origin, target }),
+        LinkMode::Hardlink => actions.push(Action::Hardlink { origin, target }),
+        LinkMode::Copy => actions.push(Action::Copy { origin, target }),
     }
+    actions.extend(hook(HookKind::PostLink));
+    Ok(actions)
 }
 
-fn ensure_path_ok(full_path: &Path) -> Result<()> {
+fn ensure_path_ok(full_path: &Path) -> Result<(), MageError> {
     let parent = full_path.parent().context("get parent path")?;
     if !parent.exists() {
-        fs::create_dir_all(parent)?;
+        fs::create_dir_all(parent).map_err(|e| MageError::io(e, parent, "create directory"))?;
         debug!(path = ?parent, "created");
     }
 
     Ok(())
 }
 
-pub fn configure<T>(programs: T) -> Vec<anyhow::Result<()>>
+/// Creates the missing parent directories of `target`, returns the created ones so
+/// that clean can remove them again
+fn create_parents(target: &Path) -> Result<Vec<PathBuf>, MageError> {
+    let parent = target.parent().context("get parent path")?;
+    let missing = parent
+        .ancestors()
+        .take_while(|dir| !dir.exists())
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+
+    if !missing.is_empty() {
+        fs::create_dir_all(parent).map_err(|e| MageError::io(e, parent, "create directory"))?;
+        debug!(path = ?parent, "created");
+    }
+
+    Ok(missing)
+}
+
+/// Links `programs` in parallel, returns what was done with the links to record
+pub fn configure<T>(programs: T, state: &State) -> (Summary, Vec<Link>)
 where
     T: IntoParallelIterator<Item = ProgramOptions>,
 {
     let span = debug_span!("configure");
     let _guard = span.enter();
 
-    programs
+    let results: Vec<_> = programs
         .into_par_iter()
         .map(|program| {
             let span = debug_span!("program", origin = ?program.origin_path);
             let _guard = span.enter();
-            program.configure()?;
+            let configured = program
+                .configure(state)
+                .map_err(anyhow::Error::from)
+                .inspect_err(|e| output::report(Record::failed("link", e).program(&program)))?;
             debug!("done");
-            Ok(())
+            Ok(configured)
         })
-        .collect()
+        .collect();
+
+    let mut summary = Summary::default();
+    let mut links = vec![];
+    for result in results {
+        summary.add(result.map(|(outcome, link)| {
+            links.extend(link);
+            outcome
+        }));
+    }
+    (summary, links)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::util::test_context::Ctx;
+    use crate::dotfiles::{Hook, Hooks};
+    use crate::util::{test_context::Ctx, FullPath};
 
     #[test]
     fn test_configure_program_with_file() {
         let ctx = Ctx::default();
 
-        assert!(ctx.opts.configure().is_ok());
-        assert!(&ctx.target_file.clone().unwrap().exists());
+        let (outcome, link) = ctx.opts.configure(&State::default()).unwrap();
+        let link = link.unwrap();
+        let target_file = ctx.target_file.clone().unwrap();
+        assert_eq!(outcome, Outcome::Done);
+        assert!(target_file.exists());
+        assert_eq!(link.target, target_file);
+    }
+
+    #[test]
+    fn test_configure_existing_is_not_recorded() {
+        let ctx = Ctx::default();
+        ctx.opts.configure(&State::default()).unwrap();
+
+        let (outcome, link) = ctx.opts.configure(&State::default()).unwrap();
+        assert_eq!(outcome, Outcome::Unchanged);
+        assert!(link.is_none());
+    }
+
+    #[test]
+    fn test_configure_blocked_target() {
+        let ctx = Ctx::default();
+        fs::write(&ctx.opts.target_path, "").unwrap();
+
+        let err = ctx.opts.configure(&State::default()).unwrap_err();
+        assert!(matches!(err, MageError::TargetConflict { .. }));
+        assert!(!ctx.target_file.clone().unwrap().is_symlink());
+    }
+
+    #[test]
+    fn test_configure_missing_origin() {
+        let mut ctx = Ctx::default();
+        ctx.opts.origin_path = FullPath::from("/tmp/mage-missing-origin");
+
+        let err = ctx.opts.configure(&State::default()).unwrap_err();
+        assert!(matches!(
+            &err,
+            MageError::MissingOrigin { origin } if origin == Path::new("/tmp/mage-missing-origin")
+        ));
+        assert!(err.hint().is_some());
+    }
+
+    #[test]
+    fn test_plan() {
+        let ctx = Ctx::default();
+        let origin = ctx.opts.origin_path.as_path().to_path_buf();
+        let target = ctx.opts.target_path.as_path().to_path_buf();
+
+        let actions = plan(&ctx.opts, LinkStatus::Missing, None).unwrap();
+        assert_eq!(
+            actions,
+            vec![Action::Symlink {
+                origin: origin.clone(),
+                target: target.clone()
+            }]
+        );
+
+        assert!(plan(&ctx.opts, LinkStatus::Linked, None)
+            .unwrap()
+            .is_empty());
+        assert!(plan(&ctx.opts, LinkStatus::Blocked, None).is_err());
+
+        let backup_dir = Path::new("/backups");
+        let actions = plan(&ctx.opts, LinkStatus::Blocked, Some(backup_dir)).unwrap();
+        assert_eq!(
+            actions[0],
+            Action::Backup {
+                target: target.clone(),
+                backup: backup_dir.join(target.strip_prefix("/").unwrap())
+            }
+        );
+        assert!(!ctx.target_file.clone().unwrap().exists());
+    }
+
+    /// Ctx with a writable origin file in `mode`
+    fn ctx_with_mode(mode: LinkMode) -> Ctx {
+        let mut ctx = Ctx::default();
+        let dir = PathBuf::from(format!("{}.d", ctx.opts.target_path));
+        let origin = dir.join("origin");
+        fs::create_dir(&dir).unwrap();
+        fs::write(&origin, "a").unwrap();
+        ctx.opts.origin_path = origin.into();
+        ctx.opts.mode = mode;
+        ctx.set_target_dir(dir);
+        ctx
+    }
+
+    #[test]
+    fn test_configure_copy() {
+        let ctx = ctx_with_mode(LinkMode::Copy);
+        let target = ctx.opts.target_path.as_path();
+        let mut state = State::default();
+
+        state.insert(ctx.opts.configure(&state).unwrap().1.unwrap());
+        assert!(!target.is_symlink());
+        assert_eq!(fs::read_to_string(target).unwrap(), "a");
+        assert!(ctx.opts.configure(&state).unwrap().1.is_none());
+
+        fs::write(ctx.opts.origin_path.as_path(), "b").unwrap();
+        assert_eq!(ctx.opts.recorded_status(&state), LinkStatus::Outdated);
+        state.insert(ctx.opts.configure(&state).unwrap().1.unwrap());
+        assert_eq!(fs::read_to_string(target).unwrap(), "b");
+
+        fs::write(target, "edited").unwrap();
+        assert_eq!(ctx.opts.recorded_status(&state), LinkStatus::Blocked);
+        assert!(ctx.opts.configure(&state).is_err());
+    }
+
+    #[test]
+    fn test_configure_hardlink() {
+        let ctx = ctx_with_mode(LinkMode::Hardlink);
+
+        let link = ctx.opts.configure(&State::default()).unwrap().1.unwrap();
+        assert!(link.is_linked());
+        assert_eq!(ctx.opts.link_status(), LinkStatus::Linked);
+        assert_eq!(
+            plan(&ctx.opts, LinkStatus::Missing, None).unwrap(),
+            vec![Action::Hardlink {
+                origin: ctx.opts.origin_path.as_path().to_path_buf(),
+                target: ctx.opts.target_path.as_path().to_path_buf(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_copy_directory_fails() {
+        let mut ctx = Ctx::default();
+        ctx.opts.mode = LinkMode::Copy;
+
+        let err = ctx.opts.configure(&State::default()).unwrap_err();
+        assert!(err.to_string().contains("is a directory"));
+    }
+
+    /// Ctx whose program has the `post_link` hook `command`
+    fn ctx_with_hook(command: &str) -> Ctx {
+        let mut ctx = Ctx::default();
+        let hooks = Hooks {
+            pre_link: Some("test ! -e \"$MAGE_TARGET\"".into()),
+            post_link: Some(command.into()),
+            ..Default::default()
+        };
+        let opts = &ctx.opts;
+        ctx.opts.hooks = Hook::entry(
+            &hooks,
+            Path::new("/tmp"),
+            opts.origin_path.as_path(),
+            opts.target_path.as_path(),
+        );
+        ctx
+    }
+
+    #[test]
+    fn test_configure_hooks() {
+        let ctx = ctx_with_hook("test -L \"$MAGE_TARGET\"");
+        let actions = plan(&ctx.opts, LinkStatus::Missing, None).unwrap();
+        assert_eq!(
+            actions[0],
+            Action::Hook("pre_link hook `test ! -e \"$MAGE_TARGET\"`".into())
+        );
+        assert_eq!(
+            actions[2],
+            Action::Hook("post_link hook `test -L \"$MAGE_TARGET\"`".into())
+        );
+
+        assert!(ctx.opts.configure(&State::default()).unwrap().1.is_some());
+
+        let ctx = ctx_with_hook("exit 1");
+        let err = ctx.opts.configure(&State::default()).unwrap_err();
+        assert!(err.to_string().contains("post_link hook `exit 1` failed"));
+        assert!(!ctx.target_file.clone().unwrap().is_symlink());
+    }
+
+    /// Gives the program of `ctx` a failing post_link hook
+    fn fail_post_link(ctx: &mut Ctx) {
+        let hooks = Hooks {
+            post_link: Some("exit 1".into()),
+            ..Default::default()
+        };
+        let opts = &ctx.opts;
+        ctx.opts.hooks = Hook::entry(
+            &hooks,
+            Path::new("/tmp"),
+            opts.origin_path.as_path(),
+            opts.target_path.as_path(),
+        );
+    }
+
+    #[test]
+    fn test_failed_link_removes_created_dirs() {
+        let mut ctx = Ctx::default();
+        let dir = PathBuf::from(format!("{}.d", ctx.opts.target_path));
+        ctx.opts.target_path = dir.join("nested/target").into();
+        ctx.set_target_dir(dir.clone());
+        fail_post_link(&mut ctx);
+
+        assert!(ctx.opts.configure(&State::default()).is_err());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_failed_link_restores_outdated_copy() {
+        let mut ctx = ctx_with_mode(LinkMode::Copy);
+        let target = ctx.opts.target_path.as_path().to_path_buf();
+        let mut state = State::default();
+        state.insert(ctx.opts.configure(&state).unwrap().1.unwrap());
+
+        fs::write(ctx.opts.origin_path.as_path(), "b").unwrap();
+        fail_post_link(&mut ctx);
+        assert!(ctx.opts.configure(&state).is_err());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "a");
+        assert_eq!(ctx.opts.recorded_status(&state), LinkStatus::Outdated);
+    }
+
+    #[test]
+    fn test_configure_template() {
+        let mut ctx = Ctx::default();
+        let origin = PathBuf::from("examples/test-dotfiles/example.config")
+            .canonicalize()
+            .unwrap();
+        let output_dir = PathBuf::from(format!("{}.rendered", ctx.opts.target_path));
+        let output = output_dir.join("example.config");
+        ctx.opts.origin_path = origin.clone().into();
+        ctx.opts.template = Some(Template {
+            output: output.clone(),
+            variables: Default::default(),
+        });
+        ctx.set_target_dir(output_dir);
+
+        let mut state = State::default();
+        let link = ctx.opts.configure(&state).unwrap().1.unwrap();
+        let target = ctx.target_file.clone().unwrap();
+        assert_eq!(fs::read_link(&target).unwrap(), output);
+        assert_eq!(fs::read(&output).unwrap(), fs::read(&origin).unwrap());
+        state.insert(link);
+
+        fs::write(&output, "edited").unwrap();
+        let err = ctx.opts.configure(&state).unwrap_err().to_string();
+        assert!(err.ends_with("was edited by hand, not overwriting it"));
     }
 
     #[test]
@@ -85,10 +589,11 @@ mod tests {
         let target_file = ctx.target_file.clone().unwrap();
         assert!(!target_file.exists());
         let programs = vec![ctx.opts.clone()];
-        let configured = configure(programs);
+        let (summary, links) = configure(programs, &State::default());
 
-        assert!(configured.get(0).unwrap().is_ok());
-        assert_eq!(configured.len(), 1);
+        assert_eq!(summary.succeeded, 1);
+        assert_eq!(summary.failed, 0);
+        assert_eq!(links.len(), 1);
         assert!(target_file.exists());
         assert!(target_file.is_symlink());
     }
@@ -1,22 +1,68 @@
 use crate::{
-    dotfiles::{ensure_repo_is_setup, find_magefile, DotfilesOrigin, ProgramOptions},
+    dotfiles::{
+        ensure_clone_of, ensure_repo_is_setup, find_magefile, DotfilesOrigin, ProgramOptions,
+    },
+    plan::{Action, Plan},
     util::FullPath,
 };
 use anyhow::Result;
-use tracing::debug_span;
+use tracing::{debug, debug_span};
 
-pub fn run(directory_or_repository: &str) -> Result<Vec<ProgramOptions>> {
+/// Clones a missing repository and returns the programs to link
+pub fn run(origin: DotfilesOrigin, profile: Option<&str>) -> Result<Vec<ProgramOptions>> {
     debug_span!("init").in_scope(|| {
-        let full_path = init_dir(directory_or_repository)?;
-        let magefile = find_magefile(full_path.as_ref())?;
-        ProgramOptions::generate(magefile, full_path)
+        let full_path = ensure_repo_is_setup(origin)?;
+        programs(full_path, profile)
     })
 }
 
-/// Clones repository or uses local directory
-fn init_dir(directory: &str) -> Result<FullPath> {
-    let origin: DotfilesOrigin = directory.parse()?;
-    ensure_repo_is_setup(origin)
+/// Like `run`, but plans the clone instead of cloning a missing repository
+pub fn plan(
+    origin: DotfilesOrigin,
+    profile: Option<&str>,
+    plan: &mut Plan,
+) -> Result<Vec<ProgramOptions>> {
+    debug_span!("init").in_scope(|| {
+        let full_path = match origin {
+            DotfilesOrigin::Repository(url, path, reference) if !path.as_path().exists() => {
+                let args = ["clone", "--recurse-submodules", &url, path.to_str()];
+                plan.push(Action::git(&args));
+                if let Some(reference) = reference {
+                    plan.push(Action::git(&["-C", path.to_str(), "checkout", &reference]));
+                }
+                return Ok(vec![]);
+            }
+            DotfilesOrigin::Repository(url, path, Some(reference)) => {
+                ensure_clone_of(&url, &path)?;
+                plan.push(Action::git(&["-C", path.to_str(), "fetch"]));
+                plan.push(Action::git(&["-C", path.to_str(), "checkout", &reference]));
+                path
+            }
+            DotfilesOrigin::Repository(url, path, None) => {
+                ensure_clone_of(&url, &path)?;
+                path
+            }
+            DotfilesOrigin::Directory(path) => path,
+        };
+        programs(full_path, profile)
+    })
+}
+
+/// Programs in the magefile that should be linked on this machine
+pub fn programs(full_path: FullPath, profile: Option<&str>) -> Result<Vec<ProgramOptions>> {
+    let magefile = find_magefile(full_path.as_ref())?;
+    let programs = ProgramOptions::generate_profile(magefile, full_path, profile)?
+        .into_iter()
+        .filter(|program| match program.skip_reason() {
+            Some(reason) => {
+                debug!(origin = ?program.origin_path, reason, "skip");
+                false
+            }
+            None => true,
+        })
+        .collect();
+
+    Ok(programs)
 }
 
 #[cfg(test)]
@@ -27,15 +73,16 @@ mod tests {
 
     #[test]
     fn link_init_with_invalid_args() {
-        // Invalid origin
-        let result = run("sdfdsf");
+        // No magefile
+        let result = run(DotfilesOrigin::Directory("src".into()), None);
         assert!(result.is_err());
     }
 
     #[test]
     fn link_init_with_valid_args() {
         let mut _ctx = Ctx::default();
-        let programs = run("examples/test-dotfiles").unwrap();
+        let origin = DotfilesOrigin::Directory("examples/test-dotfiles".into());
+        let programs = run(origin, None).unwrap();
 
         assert_eq!(programs.len(), 1);
     }
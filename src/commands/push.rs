@@ -0,0 +1,237 @@
+use anyhow::{ensure, Result};
+use std::path::Path;
+use tracing::{debug, debug_span};
+
+use crate::commands::sync::Syncer;
+use crate::dotfiles::{find_magefile, ProgramOptions};
+use crate::git::{self, Change, ChangeKind, GitBackend};
+use crate::output::{self, Outcome, Record};
+use crate::plan::{Action, Plan};
+use crate::util::FullPath;
+
+pub(crate) fn execute(
+    directory: &str,
+    message: Option<&str>,
+    all: bool,
+    dry_run: bool,
+) -> Result<()> {
+    push(
+        directory,
+        message,
+        all,
+        dry_run,
+        Syncer::with_dir(directory),
+    )
+}
+
+fn push(
+    directory: &str,
+    message: Option<&str>,
+    all: bool,
+    dry_run: bool,
+    syncer: Syncer,
+) -> Result<()> {
+    let span = debug_span!("push");
+    let _guard = span.enter();
+    let full_path: FullPath = directory.into();
+
+    ensure!(full_path.as_ref().exists(), "invalid path: {}", full_path);
+
+    let magefile = find_magefile(full_path.as_ref())?;
+    let programs = ProgramOptions::generate(magefile, full_path.clone())?;
+    let git = git::backend();
+    let changes = git.status(full_path.as_path())?;
+
+    if changes.is_empty() {
+        return push_commits(&full_path, git.as_ref(), dry_run, syncer);
+    }
+
+    let stray = changes
+        .iter()
+        .filter(|change| {
+            change.kind == ChangeKind::Untracked && !is_managed(&change.path, &programs, &full_path)
+        })
+        .map(|change| change.path.display().to_string())
+        .collect::<Vec<_>>();
+
+    ensure!(
+        all || stray.is_empty(),
+        "Untracked files outside of magefile entries:\n{}\nUse --all to push them anyway",
+        stray.join("\n")
+    );
+
+    let message = match message {
+        Some(message) => message.to_string(),
+        None => generate_message(&touched_entries(&changes, &programs)),
+    };
+    debug!(message = message, "commit");
+
+    if dry_run {
+        let dir = full_path.to_str();
+        let mut plan = Plan::default();
+        plan.push(Action::git(&["-C", dir, "add", "--all"]));
+        plan.push(Action::git(&["-C", dir, "commit", "-m", &message]));
+        plan.push(Action::git(&["-C", dir, "push"]));
+        output::plan(plan);
+        return Ok(());
+    }
+
+    syncer.push(Some(&message))?;
+    output::report(
+        Record::new("push", Outcome::Done)
+            .detail(message)
+            .text("pushed ✔️"),
+    );
+
+    Ok(())
+}
+
+/// Pushes the commits that are not on the upstream yet, without committing anything
+fn push_commits(
+    full_path: &FullPath,
+    git: &dyn GitBackend,
+    dry_run: bool,
+    syncer: Syncer,
+) -> Result<()> {
+    // Without an upstream there is nothing to push to
+    let (ahead, _) = git.divergence(full_path.as_path()).unwrap_or_default();
+    if ahead == 0 {
+        output::report(Record::new("push", Outcome::Unchanged).text("Nothing to push ✔️"));
+        return Ok(());
+    }
+
+    if dry_run {
+        let mut plan = Plan::default();
+        plan.push(Action::git(&["-C", full_path.to_str(), "push"]));
+        output::plan(plan);
+        return Ok(());
+    }
+
+    syncer.push(None)?;
+    output::report(
+        Record::new("push", Outcome::Done)
+            .detail(format!("ahead of upstream by {ahead}"))
+            .text("pushed local commits ✔️"),
+    );
+
+    Ok(())
+}
+
+fn is_managed(path: &Path, programs: &[ProgramOptions], base_path: &FullPath) -> bool {
+    let is_magefile = path
+        .strip_prefix(base_path)
+        .is_ok_and(|p| p.to_string_lossy().starts_with("magefile"));
+
+    is_magefile || programs.iter().any(|p| path.starts_with(&p.origin_path))
+}
+
+/// Names of the magefile entries that contain changes
+fn touched_entries(changes: &[Change], programs: &[ProgramOptions]) -> Vec<String> {
+    let mut entries = programs
+        .iter()
+        .filter(|p| changes.iter().any(|c| c.path.starts_with(&p.origin_path)))
+        .map(|p| p.entry.clone())
+        .collect::<Vec<_>>();
+    entries.sort();
+    entries
+}
+
+fn generate_message(entries: &[String]) -> String {
+    if entries.is_empty() {
+        return "Update dotfiles".to_string();
+    }
+
+    format!("Update {}", entries.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::git::test_repo::{self, commit, git};
+
+    /// Creates a bare repository and a clone of it with one magefile entry
+    fn setup(name: &str) -> (PathBuf, PathBuf) {
+        let (remote, local) = test_repo::setup(&format!("push-{name}"));
+        fs::write(
+            local.join("magefile.toml"),
+            "[\"example.config\"]\ntarget_path = \"/tmp/example.config\"\n",
+        )
+        .unwrap();
+        commit(&local, "example.config", "");
+        git(&local, &["push", "-q"]);
+
+        (remote, local)
+    }
+
+    #[test]
+    fn pushes_changes_to_bare_repo() {
+        let (remote, local) = setup("changes");
+        fs::write(local.join("example.config"), "changed").unwrap();
+
+        execute(local.to_str().unwrap(), None, false, false).unwrap();
+
+        let subject = git(&remote, &["log", "-1", "--format=%s"]);
+        fs::remove_dir_all(remote.parent().unwrap()).unwrap_or_default();
+        assert_eq!(subject.trim(), "Update example.config");
+    }
+
+    #[test]
+    fn pushes_local_commits_of_clean_tree() {
+        let (remote, local) = setup("commits");
+        commit(&local, "example.config", "committed");
+        let dir = local.to_str().unwrap();
+
+        let syncer = Syncer {
+            push_fn: Box::new(|_, _| panic!("should not push on dry run")),
+            ..Syncer::with_dir(dir)
+        };
+        push(dir, None, false, true, syncer).unwrap();
+
+        execute(dir, None, false, false).unwrap();
+        let remote_head = git(&remote, &["rev-parse", "HEAD"]);
+        let local_head = git(&local, &["rev-parse", "HEAD"]);
+        fs::remove_dir_all(remote.parent().unwrap()).unwrap_or_default();
+        assert_eq!(remote_head, local_head);
+    }
+
+    #[test]
+    fn refuses_untracked_files_outside_entries() {
+        let (remote, local) = setup("untracked");
+        fs::write(local.join("secret.txt"), "").unwrap();
+        let dir = local.to_str().unwrap();
+
+        let syncer = Syncer {
+            push_fn: Box::new(|_, _| panic!("should not push")),
+            ..Syncer::with_dir(dir)
+        };
+        let err = push(dir, None, false, false, syncer)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("secret.txt"));
+
+        let syncer = Syncer {
+            push_fn: Box::new(|_, message| {
+                assert_eq!(message, Some("custom"));
+                Ok(())
+            }),
+            ..Syncer::with_dir(dir)
+        };
+        push(dir, Some("custom"), true, false, syncer).unwrap();
+
+        let syncer = Syncer {
+            push_fn: Box::new(|_, _| panic!("should not push on dry run")),
+            ..Syncer::with_dir(dir)
+        };
+        push(dir, None, true, true, syncer).unwrap();
+        fs::remove_dir_all(remote.parent().unwrap()).unwrap_or_default();
+    }
+
+    #[test]
+    fn message_lists_entries() {
+        assert_eq!(generate_message(&[]), "Update dotfiles");
+        let entries = vec![".bashrc".to_string(), "nvim".to_string()];
+        assert_eq!(generate_message(&entries), "Update .bashrc, nvim");
+    }
+}
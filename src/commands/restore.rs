@@ -0,0 +1,232 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use tracing::{debug, debug_span};
+
+use crate::output::{self, Outcome, Record};
+use crate::plan::{Action, Plan};
+use crate::state::{Backup, Link, State};
+use crate::summary::Summary;
+use crate::util::move_path;
+
+pub(crate) fn execute(dry_run: bool) -> Result<()> {
+    let mut state = State::load()?;
+
+    if dry_run {
+        output::plan(plan(&state));
+        return Ok(());
+    }
+
+    let summary = restore(&mut state);
+    state.save()?;
+    summary.finish()
+}
+
+/// Plans moving the backups back without touching anything
+fn plan(state: &State) -> Plan {
+    let mut plan = Plan::default();
+
+    for backup in newest(state.backups()) {
+        if state.link(&backup.target).is_some_and(Link::is_linked) {
+            plan.push(Action::DeleteSymlink(backup.target.clone()));
+        }
+        plan.push(Action::Restore {
+            backup: backup.backup,
+            target: backup.target,
+        });
+    }
+
+    plan
+}
+
+/// Moves the newest backup of every target in `state` back to its original location.
+/// Older backups of the same target are left where they are.
+fn restore(state: &mut State) -> Summary {
+    let span = debug_span!("restore");
+    let _guard = span.enter();
+
+    let backups = state.backups();
+    newest(backups.clone())
+        .iter()
+        .map(|backup| {
+            restore_backup(backup, state).inspect_err(|e| {
+                output::report(Record::failed("restore", e).target(&backup.target));
+            })?;
+            output::report(
+                Record::new("restore", Outcome::Done)
+                    .target(&backup.target)
+                    .detail(backup.backup.display())
+                    .text(format!("{} restored ✔️", backup.target.display())),
+            );
+
+            for older in backups.iter().filter(|b| b.target == backup.target) {
+                state.remove_backup(older);
+                if older != backup {
+                    output::report(
+                        Record::new("restore", Outcome::Skipped)
+                            .target(&older.target)
+                            .detail(format!("older backup kept at {}", older.backup.display()))
+                            .text(format!(
+                                "Older backup of {} kept at {}",
+                                older.target.display(),
+                                older.backup.display()
+                            )),
+                    );
+                }
+            }
+            Ok(Outcome::Done)
+        })
+        .collect()
+}
+
+/// The newest backup of each target, in the order the targets were first backed up
+fn newest(backups: Vec<Backup>) -> Vec<Backup> {
+    let mut newest: Vec<Backup> = vec![];
+    for backup in backups {
+        match newest.iter_mut().find(|b| b.target == backup.target) {
+            // Later backups in the same second are newer too
+            Some(b) if b.created_at <= backup.created_at => *b = backup,
+            Some(_) => {}
+            None => newest.push(backup),
+        }
+    }
+    newest
+}
+
+fn restore_backup(backup: &Backup, state: &mut State) -> Result<()> {
+    let target = backup.target.as_path();
+
+    if fs::symlink_metadata(target).is_ok() {
+        // Only a link created by mage that nobody replaced since may be replaced
+        if !state.link(target).is_some_and(Link::is_linked) {
+            bail!(
+                "{} exists and was not linked by mage, not restoring {}",
+                target.display(),
+                backup.backup.display()
+            );
+        }
+        fs::remove_file(target).context(format!("delete link {}", target.display()))?;
+        state.remove(target);
+        debug!(symlink = ?target, "delete");
+    }
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).context("create parent directory")?;
+    }
+    move_path(&backup.backup, target).context(format!("move {} back", backup.backup.display()))?;
+    debug!(backup = ?backup.backup, target = ?target, "moved");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::symlink, path::PathBuf};
+
+    use super::*;
+    use crate::state::now;
+
+    #[test]
+    fn restores_backup_over_mage_symlink() {
+        let target = PathBuf::from("/tmp/mage-restore-test.config");
+        let backup_path = PathBuf::from("/tmp/mage-restore-test.config.backup");
+        fs::remove_file(&target).unwrap_or_default();
+        fs::write(&backup_path, "distro config").unwrap();
+        symlink("/tmp", &target).unwrap();
+
+        let mut state = State::default();
+        state.insert(Link::new(&"/tmp".into(), &target.clone().into()));
+        state.insert_backup(Backup {
+            target: target.clone(),
+            backup: backup_path.clone(),
+            created_at: now(),
+        });
+
+        restore(&mut state);
+        let content = fs::read_to_string(&target).unwrap();
+        fs::remove_file(&target).unwrap();
+
+        assert_eq!(content, "distro config");
+        assert!(!backup_path.exists());
+        assert!(state.backups().is_empty());
+        assert!(state.link(&target).is_none());
+    }
+
+    #[test]
+    fn does_not_overwrite_unknown_file() {
+        let target = PathBuf::from("/tmp/mage-restore-conflict.config");
+        let backup_path = PathBuf::from("/tmp/mage-restore-conflict.config.backup");
+        fs::write(&target, "new config").unwrap();
+        fs::write(&backup_path, "distro config").unwrap();
+
+        let mut state = State::default();
+        state.insert_backup(Backup {
+            target: target.clone(),
+            backup: backup_path.clone(),
+            created_at: now(),
+        });
+
+        let summary = restore(&mut state);
+        let content = fs::read_to_string(&target).unwrap();
+        fs::remove_file(&target).unwrap();
+        fs::remove_file(&backup_path).unwrap();
+
+        assert_eq!(summary.failed, 1);
+        assert_eq!(content, "new config");
+        assert_eq!(state.backups().len(), 1);
+    }
+
+    #[test]
+    fn does_not_overwrite_replaced_symlink() {
+        let target = PathBuf::from("/tmp/mage-restore-replaced.config");
+        let backup_path = PathBuf::from("/tmp/mage-restore-replaced.config.backup");
+        fs::remove_file(&target).unwrap_or_default();
+        fs::write(&backup_path, "distro config").unwrap();
+        symlink("/etc", &target).unwrap();
+
+        let mut state = State::default();
+        state.insert(Link::new(&"/tmp".into(), &target.clone().into()));
+        state.insert_backup(Backup {
+            target: target.clone(),
+            backup: backup_path.clone(),
+            created_at: now(),
+        });
+
+        let summary = restore(&mut state);
+        let replaced = fs::read_link(&target).unwrap();
+        fs::remove_file(&target).unwrap();
+        fs::remove_file(&backup_path).unwrap();
+
+        assert_eq!(summary.failed, 1);
+        assert_eq!(replaced, PathBuf::from("/etc"));
+        assert_eq!(state.backups().len(), 1);
+    }
+
+    #[test]
+    fn restores_newest_backup() {
+        let target = PathBuf::from("/tmp/mage-restore-newest.config");
+        let older = PathBuf::from("/tmp/mage-restore-newest.config.1");
+        let newer = PathBuf::from("/tmp/mage-restore-newest.config.2");
+        fs::remove_file(&target).unwrap_or_default();
+        fs::write(&older, "distro config").unwrap();
+        fs::write(&newer, "previous config").unwrap();
+
+        let mut state = State::default();
+        for (backup, created_at) in [(&newer, 2), (&older, 1)] {
+            state.insert_backup(Backup {
+                target: target.clone(),
+                backup: backup.clone(),
+                created_at,
+            });
+        }
+
+        let summary = restore(&mut state);
+        let content = fs::read_to_string(&target).unwrap();
+        fs::remove_file(&target).unwrap();
+
+        assert_eq!((summary.succeeded, summary.failed), (1, 0));
+        assert_eq!(content, "previous config");
+        assert!(older.exists());
+        fs::remove_file(&older).unwrap();
+        assert!(state.backups().is_empty());
+    }
+}
@@ -0,0 +1,313 @@
+use anyhow::{ensure, Result};
+use std::fs;
+use tracing::debug_span;
+
+use crate::config::Config;
+use crate::dotfiles::{
+    declared_packages, find_magefile, layers, merge, Layer, LinkStatus, Package, ProgramOptions,
+};
+use crate::output::{self, Outcome, Record};
+use crate::state::State;
+use crate::util::hash;
+
+/// Shows the status of the dotfiles in `dotfiles_path`, or of the sources in the user
+/// config without one
+pub(crate) fn execute(dotfiles_path: Option<&str>, config: &Config) -> Result<()> {
+    let span = debug_span!("status");
+    let _guard = span.enter();
+
+    let layers = layers(dotfiles_path, None, config)?;
+    let mut programs = vec![];
+    let mut packages = vec![];
+    for layer in &layers {
+        let full_path = layer.path();
+        ensure!(full_path.as_ref().exists(), "invalid path: {}", full_path);
+
+        let magefile = find_magefile(full_path.as_ref())?;
+        packages.extend(declared_packages(&magefile));
+        programs.push(ProgramOptions::generate(magefile, full_path.clone())?);
+    }
+    // Overridden entries of lower priority sources are not linked
+    let (programs, _) = merge(&layers, programs);
+    let state = State::load()?;
+    let rows = rows(&layers, &programs, &state);
+    output::print(table(&rows));
+    rows.iter().for_each(|row| output::report(row.record()));
+
+    let programs = programs.into_iter().map(|(_, program)| program);
+    for warning in missing_binaries(packages, programs) {
+        output::warn(warning);
+    }
+
+    let not_ok = rows.iter().filter(|row| !row.status.is_ok()).count();
+    ensure!(not_ok == 0, "{not_ok} entries are not linked correctly");
+
+    Ok(())
+}
+
+/// Packages whose binary is not on `PATH`, from the magefiles or required by the
+/// programs that are linked on this machine
+fn missing_binaries(
+    packages: Vec<Package>,
+    programs: impl Iterator<Item = ProgramOptions>,
+) -> Vec<String> {
+    let mut required: Vec<(Package, Vec<String>)> = packages
+        .into_iter()
+        .map(|package| (package, vec![]))
+        .collect();
+    for program in programs.filter(|p| p.skip_reason().is_none()) {
+        for package in program.requires {
+            let origin = program.origin_path.to_string();
+            match required.iter_mut().find(|(p, _)| p.name == package.name) {
+                Some((_, needed_by)) => needed_by.push(origin),
+                None => required.push((package, vec![origin])),
+            }
+        }
+    }
+
+    required
+        .into_iter()
+        .filter(|(package, _)| !package.is_installed())
+        .map(|(package, needed_by)| {
+            let mut warning = format!("{} is not on PATH", package.bin());
+            if package.bin() != package.name {
+                warning.push_str(&format!(", install {}", package.name));
+            }
+            if !needed_by.is_empty() {
+                warning.push_str(&format!(" (required by {})", needed_by.join(", ")));
+            }
+            warning
+        })
+        .collect()
+}
+
+struct Row {
+    /// Name of the source the entry is from, if it's from the user config
+    source: Option<String>,
+    entry: String,
+    target: String,
+    status: LinkStatus,
+}
+
+impl Row {
+    /// The row for json output, the table has it in text mode
+    fn record(&self) -> Record {
+        let outcome = match self.status {
+            LinkStatus::Linked => Outcome::Unchanged,
+            LinkStatus::Skipped(_) => Outcome::Skipped,
+            _ => Outcome::Failed,
+        };
+        Record::new("status", outcome)
+            .entry(&self.entry)
+            .target(&self.target)
+            .detail(&self.status)
+    }
+}
+
+fn rows(layers: &[Layer], programs: &[(usize, ProgramOptions)], state: &State) -> Vec<Row> {
+    programs
+        .iter()
+        .map(|(layer, program)| Row {
+            source: layers[*layer].name.clone(),
+            entry: program.entry.clone(),
+            target: program.target_path.to_string(),
+            status: status(program, state),
+        })
+        .collect()
+}
+
+/// Link status, with linked templates also checked for edits and outdated renders
+fn status(program: &ProgramOptions, state: &State) -> LinkStatus {
+    let status = program.recorded_status(state);
+    let Some(template) = program
+        .template
+        .as_ref()
+        .filter(|_| status == LinkStatus::Linked)
+    else {
+        return status;
+    };
+
+    let Ok(current) = fs::read(&template.output) else {
+        return LinkStatus::Outdated;
+    };
+    let recorded = state
+        .link(program.target_path.as_path())
+        .and_then(|link| link.rendered.as_ref());
+    if recorded.is_some_and(|rendered| rendered.hash != hash(&current)) {
+        return LinkStatus::Edited;
+    }
+
+    match template.render(program.origin_path.as_path()) {
+        Ok(content) if content.as_bytes() == current => LinkStatus::Linked,
+        _ => LinkStatus::Outdated,
+    }
+}
+
+fn table(rows: &[Row]) -> String {
+    let with_source = rows.iter().any(|r| r.source.is_some());
+    let columns = |row: &Row| {
+        let mut columns = vec![
+            row.entry.clone(),
+            row.target.clone(),
+            row.status.to_string(),
+        ];
+        if with_source {
+            columns.insert(0, row.source.clone().unwrap_or_default());
+        }
+        columns
+    };
+
+    let mut headers = vec!["ENTRY", "TARGET", "STATUS"];
+    if with_source {
+        headers.insert(0, "SOURCE");
+    }
+    let lines = std::iter::once(headers.iter().map(ToString::to_string).collect())
+        .chain(rows.iter().map(columns))
+        .collect::<Vec<Vec<String>>>();
+
+    // The last column is not padded
+    let widths = (0..headers.len() - 1)
+        .map(|i| {
+            lines
+                .iter()
+                .map(|l| l[i].chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let mut table = String::new();
+    for line in lines {
+        for (column, width) in line.iter().zip(&widths) {
+            table.push_str(&format!("{column:width$}  "));
+        }
+        table.push_str(&line[widths.len()]);
+        table.push('\n');
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::symlink, path::PathBuf};
+
+    use super::*;
+    use crate::dotfiles::{Magefile, Template};
+    use crate::state::{Link, Rendered};
+    use crate::util::test_context::Ctx;
+
+    #[test]
+    fn status_table() {
+        let rows = vec![
+            Row {
+                source: None,
+                entry: ".bashrc".into(),
+                target: "/tmp/.bashrc".into(),
+                status: LinkStatus::Linked,
+            },
+            Row {
+                source: None,
+                entry: "nvim".into(),
+                target: "/tmp/nvim".into(),
+                status: LinkStatus::Missing,
+            },
+        ];
+
+        let expected = "\
+ENTRY    TARGET        STATUS
+.bashrc  /tmp/.bashrc  linked ✔️
+nvim     /tmp/nvim     missing
+";
+        assert_eq!(table(&rows), expected);
+    }
+
+    #[test]
+    fn warns_about_missing_binaries() {
+        let magefile: Magefile = toml::from_str(
+            r#"
+            [packages.mage-editor]
+            bin = "mage-edit"
+
+            [packages.mage-tools]
+
+            ["nvim"]
+            target_path = "~/.config/nvim"
+            requires = ["mage-editor", "sh"]
+            "#,
+        )
+        .unwrap();
+        let packages = declared_packages(&magefile);
+        let programs = ProgramOptions::generate(magefile, "/dotfiles".into()).unwrap();
+
+        assert_eq!(
+            missing_binaries(packages, programs.into_iter()),
+            vec![
+                "mage-edit is not on PATH, install mage-editor (required by /dotfiles/nvim)",
+                "mage-tools is not on PATH"
+            ]
+        );
+    }
+
+    #[test]
+    fn status_table_with_sources() {
+        let row = |source: &str, entry: &str| Row {
+            source: Some(source.into()),
+            entry: entry.into(),
+            target: format!("/tmp/{entry}"),
+            status: LinkStatus::Linked,
+        };
+        let rows = vec![row("personal", ".bashrc"), row("team", ".vimrc")];
+
+        let expected = "\
+SOURCE    ENTRY    TARGET        STATUS
+personal  .bashrc  /tmp/.bashrc  linked ✔️
+team      .vimrc   /tmp/.vimrc   linked ✔️
+";
+        assert_eq!(table(&rows), expected);
+    }
+
+    #[test]
+    fn edited_template() {
+        let mut ctx = Ctx::default();
+        let output_dir = PathBuf::from(format!("{}.rendered", ctx.opts.target_path));
+        let output = output_dir.join("example.config");
+        ctx.opts.origin_path = PathBuf::from("examples/test-dotfiles/example.config")
+            .canonicalize()
+            .unwrap()
+            .into();
+        ctx.opts.template = Some(Template {
+            output: output.clone(),
+            variables: Default::default(),
+        });
+        ctx.set_target_dir(output_dir);
+
+        let content = fs::read(ctx.opts.origin_path.as_path()).unwrap();
+        fs::create_dir(output.parent().unwrap()).unwrap();
+        fs::write(&output, &content).unwrap();
+        symlink(&output, ctx.opts.target_path.as_path()).unwrap();
+
+        let mut link = Link::new(&ctx.opts.origin_path, &ctx.opts.target_path);
+        link.rendered = Some(Rendered {
+            path: output.clone(),
+            hash: hash(content),
+        });
+        let mut state = State::default();
+        state.insert(link);
+        assert_eq!(status(&ctx.opts, &state), LinkStatus::Linked);
+
+        fs::write(&output, "edited").unwrap();
+        assert_eq!(status(&ctx.opts, &state), LinkStatus::Edited);
+
+        state.remove(ctx.opts.target_path.as_path());
+        assert_eq!(status(&ctx.opts, &state), LinkStatus::Outdated);
+    }
+
+    #[test]
+    fn status_fails_when_not_linked() {
+        let err = execute(Some("examples/dotfiles"), &Config::default())
+            .unwrap_err()
+            .to_string();
+        assert!(err.ends_with("entries are not linked correctly"));
+    }
+}
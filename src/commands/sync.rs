@@ -1,62 +1,302 @@
-use std::{path::PathBuf, process::Command};
+use std::{fmt::Display, path::Path};
 
-use anyhow::Context;
+use tracing::{debug, debug_span};
 
+use crate::commands::{clean, hooks, link};
+use crate::dotfiles::{DotfilesOrigin, HookKind, Layer, ProgramOptions};
+use crate::error::MageError;
+use crate::git;
+use crate::output;
+use crate::plan::{Action, Plan};
+use crate::state::{Link, State};
+use crate::summary::Summary;
 use crate::util::FullPath;
 
-// TODO: maybe do some diffing
-pub(crate) fn execute(directory: &str) -> Result<(), anyhow::Error> {
-    let syncer = Syncer::with_dir(directory);
+mod pull;
+pub use pull::PullStrategy;
+
+pub(crate) fn execute(
+    directory: &str,
+    profile: Option<&str>,
+    strategy: PullStrategy,
+    no_hooks: bool,
+    dry_run: bool,
+) -> Result<(), anyhow::Error> {
+    let mut state = State::load()?;
+    if dry_run {
+        output::plan(plan(directory, profile, strategy, no_hooks, &state)?);
+        return Ok(());
+    }
+
+    let syncer = Syncer::with_dir(directory).with_pull(strategy, pinned_ref(directory, &state));
+    let synced = sync(directory, profile, syncer, no_hooks, &mut state);
+    state.save()?;
+    let (changes, summary) = synced?;
+    output::print(changes);
+
+    summary.finish()
+}
+
+/// Pulls the dotfiles, a failed pull stops the sync before any link is touched.
+/// Then removes the recorded links that no unchanged entry links anymore and links
+/// the new and changed entries. Untouched links are left alone.
+/// Returns the changed entries and what was done to their links
+fn sync(
+    directory: &str,
+    profile: Option<&str>,
+    syncer: Syncer,
+    no_hooks: bool,
+    state: &mut State,
+) -> anyhow::Result<(Changes, Summary)> {
+    let span = debug_span!("sync");
+    let _guard = span.enter();
+    let full_path = FullPath::from(directory);
+
+    // With a broken magefile before the pull every entry counts as changed, the
+    // recorded links are removed all the same
+    let old = link::programs(full_path.clone(), profile).unwrap_or_else(|e| {
+        debug!(error = %e, "no magefile before pull");
+        vec![]
+    });
     syncer.pull()?;
+    let mut new = link::programs(full_path.clone(), profile)?;
+    let links = recorded_links(state, &full_path, profile, &old, &new);
+    let changes = Changes::new(&old, &new, &links, full_path.as_path());
+    let magefile_hooks = hooks::load(std::slice::from_ref(&full_path), no_hooks)?;
+    if no_hooks {
+        hooks::skip(&mut new);
+    }
+    hooks::run(&magefile_hooks, HookKind::PreLink)?;
 
-    println!("Running clean...");
-    crate::commands::clean::execute(directory)?;
-    println!();
+    let mut summary = changes
+        .removed
+        .iter()
+        .map(|(_, target)| clean::unlink(target.as_path(), state))
+        .collect::<Summary>();
+    summary.extend(link::link(new, state));
+    if let Err(e) = clean::remove_empty_dirs(state) {
+        summary.add(Err(e));
+    }
+    hooks::run(&magefile_hooks, HookKind::PostLink)?;
 
-    println!("Running link...");
-    crate::commands::link::execute(directory)
+    Ok((changes, summary))
 }
 
-struct Syncer {
-    directory: FullPath,
-    pull_fn: Box<dyn FnOnce(&PathBuf) -> anyhow::Result<()>>,
+/// Links recorded for the dotfiles in `dir`. With a profile only the links of the
+/// `old` and `new` entries, those of other profiles are left alone like clean does
+fn recorded_links(
+    state: &State,
+    dir: &FullPath,
+    profile: Option<&str>,
+    old: &[ProgramOptions],
+    new: &[ProgramOptions],
+) -> Vec<Link> {
+    let mut links = state.links_from(dir.as_path());
+    if profile.is_some() {
+        links.retain(|link| {
+            old.iter()
+                .chain(new)
+                .any(|program| link.origin == program.origin_path.as_path())
+        });
+    }
+    links
 }
 
-impl Default for Syncer {
-    fn default() -> Self {
+/// Entries that the pull added or changed, and the links it removed, as entry name
+/// and target
+#[derive(Debug, Default, PartialEq)]
+struct Changes {
+    added: Vec<(String, FullPath)>,
+    removed: Vec<(String, FullPath)>,
+}
+
+impl Changes {
+    /// Entries of `new` that are not in `old` as they are count as added. The `links`
+    /// of the dotfiles in `dir` that no unchanged entry links the same way count as
+    /// removed, so an entry whose target, mode, template or hooks changed is both
+    fn new(old: &[ProgramOptions], new: &[ProgramOptions], links: &[Link], dir: &Path) -> Self {
+        let unchanged = new.iter().filter(|n| old.contains(n)).collect::<Vec<_>>();
+        let kept = |link: &Link| {
+            unchanged.iter().any(|program| {
+                link.target == program.target_path.as_path()
+                    && link.source() == program.source_path()
+                    && link.mode == program.mode
+            })
+        };
+        let entry = |link: &Link| {
+            let origin = link.origin.strip_prefix(dir).unwrap_or(&link.origin);
+            (origin.display().to_string(), FullPath::from(&link.target))
+        };
+
         Self {
-            directory: "~/.mage".into(),
-            pull_fn: Box::new(git_pull),
+            added: new
+                .iter()
+                .filter(|n| !old.contains(n))
+                .map(|program| (program.entry.clone(), program.target_path.clone()))
+                .collect(),
+            removed: links.iter().filter(|link| !kept(link)).map(entry).collect(),
+        }
+    }
+}
+
+impl Display for Changes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.added.is_empty() && self.removed.is_empty() {
+            return writeln!(f, "No entries changed ✔️");
+        }
+
+        writeln!(f, "Changes:")?;
+        for (entry, target) in &self.removed {
+            writeln!(f, "  - {entry} -> {target}")?;
         }
+        for (entry, target) in &self.added {
+            writeln!(f, "  + {entry} -> {target}")?;
+        }
+
+        Ok(())
     }
 }
 
+/// Branch, tag or commit the dotfiles in `directory` were cloned at
+fn pinned_ref(directory: &str, state: &State) -> Option<String> {
+    state
+        .checkout(FullPath::from(directory).as_path())
+        .and_then(|checkout| checkout.reference.clone())
+}
+
+/// Plans the sync, the magefile is read as it is before pulling. Links no entry
+/// links anymore are planned to be removed
+fn plan(
+    directory: &str,
+    profile: Option<&str>,
+    strategy: PullStrategy,
+    no_hooks: bool,
+    state: &State,
+) -> anyhow::Result<Plan> {
+    let full_path = FullPath::from(directory);
+    let mut plan = Plan::default();
+    let reference = pinned_ref(directory, state);
+    if let Some(reference) = &reference {
+        plan.push(Action::git(&["-C", full_path.to_str(), "fetch"]));
+        plan.push(Action::git(&[
+            "-C",
+            full_path.to_str(),
+            "checkout",
+            reference,
+        ]));
+    }
+    // A pinned tag or commit is only checked out again, a pinned branch is pulled
+    let branch = || {
+        git::backend()
+            .current_branch(full_path.as_path())
+            .ok()
+            .flatten()
+    };
+    if reference.is_none() || branch() == reference {
+        let pull = match strategy {
+            PullStrategy::Rebase => "--rebase",
+            _ => "--ff-only",
+        };
+        plan.push(Action::git(&["-C", full_path.to_str(), "pull", pull]));
+    }
+
+    let programs = link::programs(full_path.clone(), profile)?;
+    let links = recorded_links(state, &full_path, profile, &programs, &programs);
+    let changes = Changes::new(&programs, &programs, &links, full_path.as_path());
+    links
+        .into_iter()
+        .filter(|link| {
+            changes
+                .removed
+                .iter()
+                .any(|(_, t)| link.target == t.as_path())
+        })
+        .filter(Link::is_linked)
+        .for_each(|link| clean::plan_unlink(link, &mut plan));
+
+    let layer = Layer {
+        name: None,
+        origin: DotfilesOrigin::Directory(full_path),
+    };
+    plan.extend(link::plan(&[layer], false, profile, no_hooks, state)?);
+
+    Ok(plan)
+}
+
+type PullFn = Box<dyn FnOnce(&Path) -> anyhow::Result<()>>;
+type PushFn = Box<dyn FnOnce(&Path, Option<&str>) -> anyhow::Result<()>>;
+
+pub(crate) struct Syncer {
+    pub(crate) directory: FullPath,
+    pub(crate) pull_fn: PullFn,
+    pub(crate) push_fn: PushFn,
+}
+
 impl Syncer {
-    fn with_dir<D: Into<FullPath>>(directory: D) -> Self {
+    pub(crate) fn with_dir<D: Into<FullPath>>(directory: D) -> Self {
         Self {
             directory: directory.into(),
-            ..Default::default()
+            pull_fn: Box::new(|dir| {
+                pull::git_pull(git::backend().as_ref(), dir, PullStrategy::default(), None)
+            }),
+            push_fn: Box::new(git_push),
+        }
+    }
+
+    /// Pulls with `strategy`, staying on `reference` if the dotfiles are pinned to one
+    fn with_pull(self, strategy: PullStrategy, reference: Option<String>) -> Self {
+        Self {
+            pull_fn: Box::new(move |dir| {
+                let git = git::backend();
+                pull::git_pull(git.as_ref(), dir, strategy, reference.as_deref())
+            }),
+            ..self
         }
     }
 
     fn pull(self) -> anyhow::Result<()> {
-        (self.pull_fn)(&self.directory.path())
+        let dir = self.directory.path();
+        (self.pull_fn)(&dir).map_err(|source| {
+            MageError::Git {
+                command: "pull".into(),
+                dir,
+                source,
+            }
+            .into()
+        })
     }
-}
 
-fn git_pull(dir: &PathBuf) -> anyhow::Result<()> {
-    Command::new("git")
-        .arg("pull")
-        .current_dir(dir)
-        .status()
-        .context("git pull failed")?;
+    /// Commits all changes with `message` if there is one and pushes them
+    pub(crate) fn push(self, message: Option<&str>) -> anyhow::Result<()> {
+        let dir = self.directory.path();
+        (self.push_fn)(&dir, message).map_err(|source| {
+            MageError::Git {
+                command: "push".into(),
+                dir,
+                source,
+            }
+            .into()
+        })
+    }
+}
 
-    Ok(())
+fn git_push(dir: &Path, message: Option<&str>) -> anyhow::Result<()> {
+    let git = git::backend();
+    if let Some(message) = message {
+        git.commit_all(dir, message)?;
+    }
+    git.push(dir)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{fs, os::unix::fs::symlink, path::PathBuf};
+
     use super::*;
+    use crate::dotfiles::{Hook, Hooks, Template};
+    use crate::git::test_repo::{self, git};
+    use crate::state::Checkout;
+    use crate::util::test_context::Ctx;
 
     #[test]
     fn test_syncer_with_dir() {
@@ -65,4 +305,190 @@ mod tests {
 
         assert_eq!(syncer.directory.path(), PathBuf::from("/tmp"));
     }
+
+    #[test]
+    fn sync_only_touches_changed_entries() {
+        let dir = PathBuf::from("/tmp/mage-sync");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for file in ["a", "b", "c"] {
+            fs::write(dir.join(file), file).unwrap();
+        }
+        let magefile = |entries: &[&str]| {
+            entries
+                .iter()
+                .map(|e| format!("[\"{e}\"]\ntarget_path = \"/tmp/mage-sync/{e}.link\"\n"))
+                .collect::<String>()
+        };
+        fs::write(dir.join("magefile"), magefile(&["a", "b"])).unwrap();
+
+        let directory = dir.to_str().unwrap();
+        let mut state = State::default();
+        let programs = link::programs(directory.into(), None).unwrap();
+        link::link(programs, &mut state);
+        let linked_at = fs::symlink_metadata(dir.join("a.link"))
+            .unwrap()
+            .modified()
+            .unwrap();
+
+        let syncer = Syncer {
+            pull_fn: Box::new(move |dir| {
+                fs::write(dir.join("magefile"), magefile(&["a", "c"]))?;
+                Ok(())
+            }),
+            ..Syncer::with_dir(directory)
+        };
+        let (changes, summary) = sync(directory, None, syncer, false, &mut state).unwrap();
+        assert_eq!(summary.to_string(), "3 succeeded, 0 skipped, 0 failed");
+
+        assert_eq!(
+            changes.added,
+            vec![("c".into(), "/tmp/mage-sync/c.link".into())]
+        );
+        assert_eq!(
+            changes.removed,
+            vec![("b".into(), "/tmp/mage-sync/b.link".into())]
+        );
+        assert_eq!(
+            fs::symlink_metadata(dir.join("a.link"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            linked_at
+        );
+        assert!(!dir.join("b.link").exists());
+        assert!(dir.join("c.link").is_symlink());
+        assert_eq!(state.links_from(&dir).len(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn changes_compare_whole_entries() {
+        let ctx = Ctx::default();
+        let mut program = ctx.opts.clone();
+        program.entry = "a".into();
+        program.origin_path = "/dotfiles/a".into();
+        program.target_path = "/tmp/a".into();
+        let dir = Path::new("/dotfiles");
+        let link = Link::new(&program.origin_path, &program.target_path);
+        let a = || vec![("a".to_string(), FullPath::from("/tmp/a"))];
+
+        let programs = std::slice::from_ref(&program);
+        let links = [link.clone()];
+        assert_eq!(
+            Changes::new(programs, programs, &links, dir),
+            Changes::default()
+        );
+
+        let mut template = program.clone();
+        template.template = Some(Template {
+            output: "/tmp/rendered/a".into(),
+            variables: Default::default(),
+        });
+        let changes = Changes::new(programs, &[template], &links, dir);
+        assert_eq!((changes.added, changes.removed), (a(), a()));
+
+        let mut hooked = program.clone();
+        let hooks = Hooks {
+            post_link: Some("true".into()),
+            ..Default::default()
+        };
+        hooked.hooks = Hook::entry(&hooks, dir, Path::new("/dotfiles/a"), Path::new("/tmp/a"));
+        let changes = Changes::new(programs, &[hooked], &links, dir);
+        assert_eq!((changes.added, changes.removed), (a(), a()));
+
+        // Links of entries removed outside of a sync are removed too
+        let stale = Link::new(&"/dotfiles/b".into(), &"/tmp/b".into());
+        let changes = Changes::new(programs, programs, &[link, stale], dir);
+        assert_eq!(changes.added, vec![]);
+        assert_eq!(changes.removed, vec![("b".into(), "/tmp/b".into())]);
+    }
+
+    #[test]
+    fn plan_removes_stale_links() {
+        let dir = PathBuf::from("/tmp/mage-sync-plan");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a"), "a").unwrap();
+        fs::write(
+            dir.join("magefile"),
+            "[\"a\"]\ntarget_path = \"/tmp/mage-sync-plan/a.link\"\n",
+        )
+        .unwrap();
+        let directory = dir.to_str().unwrap();
+
+        let mut state = State::default();
+        link::link(link::programs(directory.into(), None).unwrap(), &mut state);
+        symlink(dir.join("b"), dir.join("b.link")).unwrap();
+        state.insert(Link::new(&dir.join("b").into(), &dir.join("b.link").into()));
+
+        let plan = plan(directory, None, PullStrategy::default(), false, &state).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(plan
+            .actions
+            .contains(&Action::DeleteSymlink(dir.join("b.link"))));
+        assert!(!plan
+            .actions
+            .contains(&Action::DeleteSymlink(dir.join("a.link"))));
+    }
+
+    #[test]
+    fn plan_does_not_pull_pinned_tag() {
+        let (remote, local) = test_repo::setup("sync-plan-pinned");
+        fs::write(local.join("magefile"), "").unwrap();
+        git(&local, &["tag", "v1"]);
+        let directory = local.to_str().unwrap();
+        let pulls = |state: &State| {
+            let plan = plan(directory, None, PullStrategy::default(), false, state).unwrap();
+            plan.actions.iter().any(
+                |action| matches!(action, Action::Git(args) if args.contains(&"pull".to_string())),
+            )
+        };
+
+        let mut state = State::default();
+        assert!(pulls(&state));
+        let mut checkout = Checkout {
+            path: local.clone(),
+            url: remote.display().to_string(),
+            reference: Some("main".into()),
+        };
+        git(&local, &["checkout", "-q", "-B", "main"]);
+        state.insert_checkout(checkout.clone());
+        assert!(pulls(&state));
+
+        checkout.reference = Some("v1".into());
+        git(&local, &["checkout", "-q", "v1"]);
+        state.insert_checkout(checkout);
+        let pulls_tag = pulls(&state);
+        fs::remove_dir_all(remote.parent().unwrap()).unwrap_or_default();
+        assert!(!pulls_tag);
+    }
+
+    #[test]
+    fn display_changes() {
+        let changes = Changes {
+            added: vec![("nvim".into(), "/tmp/nvim".into())],
+            removed: vec![(".bashrc".into(), "/tmp/.bashrc".into())],
+        };
+        assert_eq!(
+            changes.to_string(),
+            "Changes:\n  - .bashrc -> /tmp/.bashrc\n  + nvim -> /tmp/nvim\n"
+        );
+        assert_eq!(Changes::default().to_string(), "No entries changed ✔️\n");
+    }
+
+    #[test]
+    fn test_syncer_push_fn() {
+        let syncer = Syncer {
+            push_fn: Box::new(|dir, message| {
+                assert_eq!(dir, Path::new("/tmp"));
+                assert_eq!(message, Some("test"));
+                Ok(())
+            }),
+            ..Syncer::with_dir("/tmp")
+        };
+
+        syncer.push(Some("test")).unwrap();
+    }
 }
@@ -0,0 +1,299 @@
+use anyhow::{bail, ensure, Result};
+use clap::ValueEnum;
+use std::path::Path;
+use tracing::debug;
+
+use crate::git::{conflicts, is_dirty, GitBackend};
+
+/// What sync does with uncommitted changes and local commits when pulling
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum PullStrategy {
+    /// Refuse to pull over uncommitted changes or a diverged branch
+    #[default]
+    Abort,
+    /// Stash uncommitted changes during the pull and reapply them afterwards
+    Stash,
+    /// Rebase local commits onto the remote, stashing uncommitted changes
+    Rebase,
+}
+
+/// Pulls the dotfiles in `dir`, fails before changing anything if that's not possible
+/// cleanly with `strategy`. With a `reference` the dotfiles stay on that branch, tag
+/// or commit, tags and commits are only checked out again
+pub fn git_pull(
+    git: &dyn GitBackend,
+    dir: &Path,
+    strategy: PullStrategy,
+    reference: Option<&str>,
+) -> Result<()> {
+    let changes = git.status(dir)?;
+    let conflicts = conflicts(&changes, dir);
+    ensure!(
+        conflicts.is_empty(),
+        "{} has unresolved conflicts in {}, resolve them first",
+        dir.display(),
+        conflicts.join(", ")
+    );
+
+    let dirty = is_dirty(&changes);
+    if dirty && strategy == PullStrategy::Abort {
+        bail!(
+            "{} has uncommitted changes, commit them or sync with --strategy stash",
+            dir.display()
+        );
+    }
+
+    git.fetch(dir)?;
+    let switch = match reference {
+        Some(reference) if git.current_branch(dir)?.as_deref() != Some(reference) => {
+            Some(reference)
+        }
+        _ => None,
+    };
+    // Checked before switching, a new branch or a tag or commit has nothing to pull
+    let (ahead, behind) = match switch {
+        Some(reference) => git.branch_divergence(dir, reference)?.unwrap_or_default(),
+        None => git.divergence(dir)?,
+    };
+    debug!(?switch, ahead, behind, dirty, ?strategy, "pull");
+
+    if switch.is_none() && behind == 0 {
+        return Ok(());
+    }
+
+    if ahead > 0 && behind > 0 && strategy != PullStrategy::Rebase {
+        bail!(
+            "Local branch has diverged from the remote ({ahead} local and {behind} remote commits), \
+            push them first or sync with --strategy rebase"
+        );
+    }
+
+    let pull = |dir: &Path| {
+        if let Some(reference) = switch {
+            git.checkout(dir, reference)?;
+        }
+        match strategy {
+            _ if behind == 0 => Ok(()),
+            PullStrategy::Rebase => rebase(git, dir),
+            _ => git.fast_forward(dir),
+        }
+    };
+
+    if dirty {
+        with_stash(git, dir, pull)
+    } else {
+        pull(dir)
+    }
+}
+
+/// Runs `f` with the uncommitted changes stashed away
+fn with_stash(git: &dyn GitBackend, dir: &Path, f: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    git.stash(dir)?;
+
+    if let Err(e) = f(dir) {
+        return match git.stash_pop(dir) {
+            Ok(()) => Err(e),
+            Err(pop) => Err(e.context(format!(
+                "Your uncommitted changes are left in the stash, reapplying them failed: {pop}"
+            ))),
+        };
+    }
+
+    if let Err(e) = git.stash_pop(dir) {
+        let conflicts = conflicts(&git.status(dir)?, dir);
+        if conflicts.is_empty() {
+            bail!("Pulled, but your uncommitted changes could not be reapplied, they are kept in the stash: {e}");
+        }
+        bail!(
+            "Pulled, but reapplying your uncommitted changes conflicted in {}, \
+            resolve them and run `git stash drop`",
+            conflicts.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Rebases local commits onto the upstream branch, a conflicting rebase is aborted
+fn rebase(git: &dyn GitBackend, dir: &Path) -> Result<()> {
+    if let Err(e) = git.rebase(dir) {
+        let conflicts = conflicts(&git.status(dir)?, dir);
+        if conflicts.is_empty() {
+            return Err(e);
+        }
+
+        git.abort_rebase(dir)?;
+        bail!(
+            "Rebasing local commits onto the remote conflicted in {}, the rebase was aborted",
+            conflicts.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::git::{
+        backend,
+        test_repo::{self, commit, git},
+    };
+
+    /// Creates a bare repository with two clones of it, `local` and `other`
+    fn setup(name: &str) -> (PathBuf, PathBuf) {
+        let (remote, local) = test_repo::setup(&format!("pull-{name}"));
+        commit(&local, "b", "b");
+        git(&local, &["push", "-q"]);
+
+        (local, test_repo::clone(remote.parent().unwrap(), "other"))
+    }
+
+    fn pull(dir: &Path, strategy: PullStrategy) -> Result<()> {
+        git_pull(backend().as_ref(), dir, strategy, None)
+    }
+
+    fn pull_pinned(dir: &Path, reference: &str) -> Result<()> {
+        git_pull(
+            backend().as_ref(),
+            dir,
+            PullStrategy::Abort,
+            Some(reference),
+        )
+    }
+
+    fn read(dir: &Path, file: &str) -> String {
+        fs::read_to_string(dir.join(file)).unwrap()
+    }
+
+    fn cleanup(local: &Path) {
+        fs::remove_dir_all(local.parent().unwrap()).unwrap_or_default();
+    }
+
+    #[test]
+    fn fast_forwards() {
+        let (local, other) = setup("fast-forward");
+        commit(&other, "b", "remote");
+        git(&other, &["push", "-q"]);
+
+        pull(&local, PullStrategy::Abort).unwrap();
+
+        assert_eq!(read(&local, "b"), "remote");
+        cleanup(&local);
+    }
+
+    #[test]
+    fn uncommitted_changes() {
+        let (local, other) = setup("dirty");
+        commit(&other, "b", "remote");
+        git(&other, &["push", "-q"]);
+        fs::write(local.join("a"), "local").unwrap();
+
+        let err = pull(&local, PullStrategy::Abort).unwrap_err();
+        assert!(err.to_string().contains("has uncommitted changes"));
+        assert_eq!(read(&local, "b"), "b");
+
+        pull(&local, PullStrategy::Stash).unwrap();
+        assert_eq!(read(&local, "a"), "local");
+        assert_eq!(read(&local, "b"), "remote");
+        cleanup(&local);
+    }
+
+    #[test]
+    fn stashed_changes_conflict() {
+        let (local, other) = setup("stash-conflict");
+        commit(&other, "a", "remote");
+        git(&other, &["push", "-q"]);
+        fs::write(local.join("a"), "local").unwrap();
+
+        let err = pull(&local, PullStrategy::Stash).unwrap_err();
+        assert!(err.to_string().starts_with("Pulled, but"));
+        cleanup(&local);
+    }
+
+    #[test]
+    fn diverged_branch() {
+        let (local, other) = setup("diverged");
+        commit(&other, "b", "remote");
+        git(&other, &["push", "-q"]);
+        commit(&local, "a", "local");
+
+        let err = pull(&local, PullStrategy::Abort).unwrap_err();
+        assert!(err.to_string().contains("diverged"));
+
+        pull(&local, PullStrategy::Rebase).unwrap();
+        assert_eq!(read(&local, "a"), "local");
+        assert_eq!(read(&local, "b"), "remote");
+        cleanup(&local);
+    }
+
+    #[test]
+    fn rebase_conflict_is_aborted() {
+        let (local, other) = setup("rebase-conflict");
+        commit(&other, "a", "remote");
+        git(&other, &["push", "-q"]);
+        commit(&local, "a", "local");
+        let head = git(&local, &["rev-parse", "HEAD"]);
+
+        let err = pull(&local, PullStrategy::Rebase).unwrap_err();
+        assert!(err.to_string().contains("conflicted in a"));
+        assert_eq!(git(&local, &["rev-parse", "HEAD"]), head);
+        assert!(git(&local, &["status", "--porcelain"]).is_empty());
+        cleanup(&local);
+    }
+
+    #[test]
+    fn stays_on_pinned_tag() {
+        let (local, other) = setup("pinned-tag");
+        git(&other, &["tag", "v1"]);
+        git(&other, &["push", "-q", "--tags"]);
+        commit(&other, "b", "remote");
+        git(&other, &["push", "-q"]);
+
+        pull_pinned(&local, "v1").unwrap();
+        assert_eq!(read(&local, "b"), "b");
+        assert_eq!(git(&local, &["describe", "--tags"]), "v1");
+        cleanup(&local);
+    }
+
+    #[test]
+    fn diverged_pinned_branch_is_not_checked_out() {
+        let (local, other) = setup("pinned-diverged");
+        let branch = git(&local, &["branch", "--show-current"]);
+        git(&local, &["switch", "-q", "-c", "work"]);
+        commit(&local, "b", "local");
+        git(&local, &["push", "-q", "-u", "origin", "work"]);
+        git(&other, &["fetch", "-q"]);
+        git(&other, &["switch", "-q", "work"]);
+        commit(&other, "a", "remote");
+        git(&other, &["push", "-q"]);
+        commit(&local, "b", "diverged");
+        git(&local, &["switch", "-q", &branch]);
+        let head = git(&local, &["rev-parse", "HEAD"]);
+
+        let err = pull_pinned(&local, "work").unwrap_err();
+        assert!(err.to_string().contains("diverged"));
+        assert_eq!(git(&local, &["branch", "--show-current"]), branch);
+        assert_eq!(git(&local, &["rev-parse", "HEAD"]), head);
+        cleanup(&local);
+    }
+
+    #[test]
+    fn follows_pinned_branch() {
+        let (local, other) = setup("pinned-branch");
+        git(&other, &["switch", "-q", "-c", "work"]);
+        commit(&other, "b", "work");
+        git(&other, &["push", "-q", "-u", "origin", "work"]);
+
+        pull_pinned(&local, "work").unwrap();
+        assert_eq!(read(&local, "b"), "work");
+
+        commit(&other, "b", "updated");
+        git(&other, &["push", "-q"]);
+        pull_pinned(&local, "work").unwrap();
+        assert_eq!(read(&local, "b"), "updated");
+        cleanup(&local);
+    }
+}
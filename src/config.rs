@@ -0,0 +1,337 @@
+use anyhow::{anyhow, ensure, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::{fs, path::PathBuf};
+use tracing::debug;
+
+use crate::dotfiles::{DotfilesOrigin, Protocol, RepoDefaults};
+use crate::error::MageError;
+use crate::output::Format;
+use crate::util::{data_dir, FullPath};
+
+/// Location of the config file when `XDG_CONFIG_HOME` is not set
+const DEFAULT_CONFIG_DIR: &str = "~/.config";
+
+/// Location of the dotfiles when neither the command nor the config has one
+pub const DEFAULT_DIRECTORY: &str = "~/.mage";
+
+/// User settings, read from `$XDG_CONFIG_HOME/mage/config.toml` or `MAGE_CONFIG`
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// Dotfiles of the commands that get no directory, repositories are cloned to it
+    directory: Option<String>,
+    /// Host of `user/repo` shorthands
+    host: Option<String>,
+    /// Protocol of the urls built from shorthands
+    protocol: Option<Protocol>,
+    /// Magefile profile of the commands that get none
+    profile: Option<String>,
+    #[serde(default)]
+    pub output: Output,
+    #[serde(default, rename = "source")]
+    sources: Vec<Source>,
+}
+
+/// How mage prints its output
+#[derive(Debug, Default, Deserialize)]
+pub struct Output {
+    /// Show debug information, like `--debug`
+    #[serde(default)]
+    pub debug: bool,
+    /// Format of the output when `--output` is not given
+    pub format: Option<Format>,
+}
+
+/// Dotfiles that are linked together with the other sources
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Source {
+    pub name: String,
+    /// Directory or repository, like the argument of `mage link`
+    pub origin: String,
+    /// A source with a higher priority wins the targets several sources link
+    #[serde(default)]
+    pub priority: i64,
+    /// Where a repository is cloned to, `<data dir>/sources/<name>` by default
+    pub clone_dir: Option<String>,
+}
+
+impl Config {
+    /// Loads the config from `MAGE_CONFIG` or the default location, then applies the
+    /// overrides in the environment
+    pub fn load() -> Result<Self, MageError> {
+        let config = match std::env::var("MAGE_CONFIG") {
+            Ok(path) => {
+                let path = FullPath::from(path).path();
+                if !path.exists() {
+                    return Err(MageError::Config {
+                        origin: "MAGE_CONFIG".into(),
+                        source: anyhow!("Config file {} does not exist", path.display()),
+                    });
+                }
+                Self::load_from(path)?
+            }
+            Err(_) => Self::load_from(default_config_path())?,
+        };
+
+        config
+            .with_env(|var| std::env::var(var).ok())
+            .map_err(|source| MageError::Config {
+                origin: "the environment".into(),
+                source,
+            })
+    }
+
+    /// Loads the config from `path`, the default config is used if the file does not exist
+    pub fn load_from<P: Into<PathBuf>>(path: P) -> Result<Self, MageError> {
+        let path: PathBuf = path.into();
+        if !path.exists() {
+            debug!(?path, "no config file");
+            return Ok(Self::default());
+        }
+
+        fs::read_to_string(&path)
+            .context("read config file")
+            .and_then(|content| content.parse())
+            .map_err(|source| MageError::Config {
+                origin: path.display().to_string(),
+                source,
+            })
+    }
+
+    /// Applies `MAGE_DIR`, `MAGE_DEFAULT_HOST` and `MAGE_DEFAULT_PROTOCOL` from `var`
+    fn with_env(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        if let Some(directory) = var("MAGE_DIR") {
+            self.directory = Some(directory);
+        }
+        if let Some(host) = var("MAGE_DEFAULT_HOST") {
+            self.host = Some(host);
+        }
+        if let Some(protocol) = var("MAGE_DEFAULT_PROTOCOL") {
+            self.protocol = Some(protocol.parse()?);
+        }
+
+        Ok(self)
+    }
+
+    /// Dotfiles of the commands that get no directory
+    pub fn directory(&self) -> String {
+        self.directory
+            .clone()
+            .unwrap_or_else(|| DEFAULT_DIRECTORY.to_string())
+    }
+
+    /// `profile` if given, otherwise the default profile
+    pub fn profile(&self, profile: Option<String>) -> Option<String> {
+        profile.or_else(|| self.profile.clone())
+    }
+
+    pub fn repo_defaults(&self) -> RepoDefaults {
+        let defaults = RepoDefaults::default();
+        RepoDefaults {
+            host: self.host.clone().unwrap_or(defaults.host),
+            protocol: self.protocol.unwrap_or(defaults.protocol),
+            directory: self.directory(),
+        }
+    }
+
+    /// Sources from the highest priority down, sources with the same priority keep
+    /// their order in the config
+    pub fn sources(&self) -> Vec<Source> {
+        let mut sources = self.sources.clone();
+        sources.sort_by_key(|source| std::cmp::Reverse(source.priority));
+        sources
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let config: Config = toml::from_str(s)?;
+
+        let name = Regex::new(r"^\w[\w.-]*$").unwrap();
+        for (i, source) in config.sources.iter().enumerate() {
+            ensure!(
+                name.is_match(&source.name),
+                "Invalid source name {:?}, use letters, digits, '.', '-' and '_'",
+                source.name
+            );
+            ensure!(
+                !config.sources[..i].iter().any(|s| s.name == source.name),
+                "Source {} is defined twice",
+                source.name
+            );
+        }
+
+        Ok(config)
+    }
+}
+
+impl Source {
+    /// Parses the origin, a repository is cloned to the clone directory of the source
+    pub fn parse_origin(&self, defaults: &RepoDefaults) -> Result<DotfilesOrigin> {
+        let origin = DotfilesOrigin::parse(&self.origin, defaults)
+            .with_context(|| format!("Invalid origin of source {}", self.name))?;
+
+        match origin {
+            DotfilesOrigin::Repository(..) => origin.with_clone_dir(&self.clone_dir()),
+            directory => Ok(directory),
+        }
+    }
+
+    fn clone_dir(&self) -> String {
+        self.clone_dir.clone().unwrap_or_else(|| {
+            let dir = data_dir().join("sources").join(&self.name);
+            dir.to_string_lossy().to_string()
+        })
+    }
+}
+
+fn default_config_path() -> PathBuf {
+    let config_dir = std::env::var("XDG_CONFIG_HOME").unwrap_or(DEFAULT_CONFIG_DIR.to_string());
+    let mut path = FullPath::from(config_dir).path();
+    path.push("mage");
+    path.push("config.toml");
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_by_priority() {
+        let config: Config = r#"
+            [[source]]
+            name = "team"
+            origin = "acme/dotfiles#v2"
+            priority = 10
+
+            [[source]]
+            name = "personal"
+            origin = "/tmp"
+            priority = 20
+
+            [[source]]
+            name = "extra"
+            origin = "/tmp"
+            priority = 20
+        "#
+        .parse()
+        .unwrap();
+
+        let names = config
+            .sources()
+            .into_iter()
+            .map(|s| s.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["personal", "extra", "team"]);
+    }
+
+    #[test]
+    fn source_origins() {
+        let source = |origin: &str, clone_dir: Option<&str>| Source {
+            name: "team".into(),
+            origin: origin.into(),
+            priority: 0,
+            clone_dir: clone_dir.map(Into::into),
+        };
+
+        assert_eq!(
+            source("/tmp", None)
+                .parse_origin(&RepoDefaults::default())
+                .unwrap(),
+            DotfilesOrigin::Directory("/tmp".into())
+        );
+        let DotfilesOrigin::Repository(_, path, _) = source("acme/dotfiles", None)
+            .parse_origin(&RepoDefaults::default())
+            .unwrap()
+        else {
+            panic!("should be a repository");
+        };
+        assert_eq!(path.path(), data_dir().join("sources/team"));
+
+        let origin = source("acme/dotfiles", Some("/tmp/team")).parse_origin(&Default::default());
+        assert!(
+            matches!(origin.unwrap(), DotfilesOrigin::Repository(_, path, _) if path == "/tmp/team".into())
+        );
+    }
+
+    #[test]
+    fn invalid_sources() {
+        let duplicate = r#"
+            [[source]]
+            name = "team"
+            origin = "/tmp"
+
+            [[source]]
+            name = "team"
+            origin = "/tmp"
+        "#;
+        assert!(duplicate.parse::<Config>().is_err());
+
+        let invalid_name = r#"
+            [[source]]
+            name = "../team"
+            origin = "/tmp"
+        "#;
+        assert!(invalid_name.parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn defaults() {
+        let config: Config = r#"
+            directory = "~/dotfiles"
+            host = "git.example.com"
+            protocol = "https"
+            profile = "minimal"
+
+            [output]
+            debug = true
+            format = "json"
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(config.directory(), "~/dotfiles");
+        assert_eq!(config.profile(None).unwrap(), "minimal");
+        assert_eq!(config.profile(Some("full".into())).unwrap(), "full");
+        assert!(config.output.debug);
+        assert_eq!(config.output.format, Some(Format::Json));
+        assert_eq!(
+            config.repo_defaults(),
+            RepoDefaults {
+                host: "git.example.com".into(),
+                protocol: Protocol::Https,
+                directory: "~/dotfiles".into(),
+            }
+        );
+        assert!("protocol = \"ftp\"".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn environment_overrides() {
+        let config: Config = "directory = \"~/dotfiles\"".parse().unwrap();
+        let env = |var: &str| match var {
+            "MAGE_DIR" => Some("/srv/dotfiles".to_string()),
+            "MAGE_DEFAULT_PROTOCOL" => Some("https".to_string()),
+            _ => None,
+        };
+
+        let config = config.with_env(env).unwrap();
+        assert_eq!(config.directory(), "/srv/dotfiles");
+        let defaults = config.repo_defaults();
+        assert_eq!(defaults.host, "github.com");
+        assert_eq!(defaults.protocol, Protocol::Https);
+
+        let invalid = Config::default().with_env(|_| Some("ftp".to_string()));
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn missing_config_file() {
+        let config = Config::load_from("/tmp/mage-no-config/config.toml").unwrap();
+        assert!(config.sources().is_empty());
+        assert_eq!(config.directory(), DEFAULT_DIRECTORY);
+    }
+}
@@ -1,73 +1,306 @@
-use anyhow::{anyhow, ensure, Context, Result};
-use regex::Regex;
+use anyhow::{anyhow, ensure, Result};
 use std::{
+    fmt::Display,
     fs::{self},
     path::{Path, PathBuf},
-    str::FromStr,
 };
-use toml::Table;
 use tracing::{debug, debug_span};
 
-use crate::util::FullPath;
+use crate::error::MageError;
+use crate::git;
+use crate::state::{Checkout, State};
+use crate::util::{hash, same_file, FullPath};
+
+mod conditions;
+mod contents;
+mod hooks;
+mod layers;
+mod magefile;
+mod origin;
+mod packages;
+mod profiles;
+mod template;
+pub use conditions::{Conditions, Machine};
+pub use hooks::{Hook, HookKind};
+pub use layers::{layers, merge, Conflict, Layer};
+#[cfg(test)]
+pub use magefile::Hooks;
+use magefile::{is_pattern, Entry, LinkScope};
+pub use magefile::{LinkMode, Magefile};
+use origin::same_repository;
+pub use origin::{DotfilesOrigin, Protocol, RepoDefaults};
+pub use packages::{declared_packages, Package};
+use profiles::profile_entries;
+use template::variables_for;
+pub use template::Template;
 
 /// Represents one program-config in the dotfiles directory, that can be configured by mage.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ProgramOptions {
+    /// Key of the entry in the magefile, the path of the file for entries that are
+    /// expanded to several files
+    pub entry: String,
     /// Path of the config file or folder located in dotfiles also the key in magefile
     pub origin_path: FullPath,
     /// Target path for symlink
     pub target_path: FullPath,
-    // TODO: Force flag
+    /// Machines this program is linked on
+    pub conditions: Conditions,
+    /// Set if the origin is rendered before linking
+    pub template: Option<Template>,
+    pub mode: LinkMode,
+    /// Shell commands that run around linking and cleaning the program
+    pub hooks: Vec<Hook>,
+    /// Packages the program needs
+    pub requires: Vec<Package>,
 }
 
 impl ProgramOptions {
-    pub fn generate(magefile: Table, base_path: FullPath) -> Result<Vec<ProgramOptions>> {
+    pub fn generate(
+        magefile: Magefile,
+        base_path: FullPath,
+    ) -> Result<Vec<ProgramOptions>, MageError> {
         let span = debug_span!("read_config");
         let _guard = span.enter();
 
-        let keys = magefile.keys();
         let mut result = vec![];
+        let variables = variables_for(&magefile, Machine::current());
+
+        for (key, entry) in magefile.entries {
+            let conditions = Conditions {
+                hosts: entry.hosts.clone(),
+                os: entry.os.clone(),
+                env: entry.env.clone().into_iter().collect(),
+            };
+            let program = |name: String, origin_path: FullPath, target_path: FullPath| {
+                let template = entry
+                    .template
+                    .then(|| Template::new(target_path.as_path(), variables.clone()));
+                let requires = packages::resolve(&magefile.packages, &entry.requires);
+                let hooks = Hook::entry(
+                    &entry.hooks(),
+                    base_path.as_path(),
+                    origin_path.as_path(),
+                    target_path.as_path(),
+                );
+                ProgramOptions {
+                    entry: name,
+                    origin_path,
+                    target_path,
+                    conditions: conditions.clone(),
+                    template,
+                    mode: entry.mode,
+                    hooks,
+                    requires,
+                }
+            };
 
-        for origin_path in keys {
-            let item = magefile.get(origin_path).expect("should always get value");
-            let target_path = item
-                .get("target_path")
-                .context(format!("target_path not found in {item}"))?
-                .as_str()
-                .expect("should be able to convert to str")
-                .to_string();
-            let full_origin_path = get_full_origin_path(&base_path.as_ref(), &origin_path);
-            let full_target_path = FullPath::from(target_path);
-
-            let opts = ProgramOptions {
-                origin_path: full_origin_path,
-                target_path: full_target_path,
+            if !entry.ignore.is_empty() && entry.link != LinkScope::Contents {
+                return Err(MageError::InvalidEntry {
+                    key,
+                    message: "ignore needs link = \"contents\"".into(),
+                });
+            }
+            let origins = if is_pattern(&key) {
+                contents::matching(base_path.as_path(), &key)?
+            } else {
+                vec![PathBuf::from(&key)]
             };
-            result.push(opts)
+            debug!(key, matches = origins.len(), "entry");
+
+            for relative in origins {
+                let full_origin_path = get_full_origin_path(base_path.as_ref(), &relative);
+                let full_target_path = target_of(&entry, &relative);
+
+                // A missing origin is kept as one program so that it shows up as missing
+                let origin = full_origin_path.as_path();
+                if entry.link == LinkScope::Whole || !origin.exists() {
+                    let name = relative.display().to_string();
+                    result.push(program(name, full_origin_path, full_target_path));
+                    continue;
+                }
+
+                if !origin.is_dir() {
+                    return Err(MageError::InvalidEntry {
+                        key: relative.display().to_string(),
+                        message: "link = \"contents\" needs a directory".into(),
+                    });
+                }
+                for file in contents::files(origin, &entry.ignore)? {
+                    result.push(program(
+                        relative.join(&file).display().to_string(),
+                        FullPath::from(origin.join(&file)),
+                        FullPath::from(full_target_path.as_path().join(&file)),
+                    ));
+                }
+            }
         }
 
         Ok(result)
     }
+
+    /// Like `generate`, but only the programs in `profile` if one is given
+    pub fn generate_profile(
+        magefile: Magefile,
+        base_path: FullPath,
+        profile: Option<&str>,
+    ) -> Result<Vec<ProgramOptions>, MageError> {
+        let Some(profile) = profile else {
+            return Self::generate(magefile, base_path);
+        };
+
+        let entries = profile_entries(&magefile, profile)?;
+        debug!(profile, entries = ?entries, "selected");
+        let mut magefile = magefile;
+        magefile.entries.retain(|name, _| entries.contains(name));
+
+        Self::generate(magefile, base_path)
+    }
+}
+
+/// State of the symlink for one program
+#[derive(Debug, PartialEq)]
+pub enum LinkStatus {
+    /// Target is a symlink to the origin
+    Linked,
+    /// Nothing exists at the target
+    Missing,
+    /// Target is a regular file or directory
+    Blocked,
+    /// Target is a symlink to some other existing path
+    WrongTarget(PathBuf),
+    /// Target is a symlink to a path that does not exist
+    Dangling(PathBuf),
+    /// Origin does not exist in the dotfiles
+    OriginMissing,
+    /// Conditions of the entry don't match this machine
+    Skipped(String),
+    /// Rendered template was changed by hand since mage wrote it
+    Edited,
+    /// Rendered template or copy made by mage differs from the origin
+    Outdated,
+}
+
+impl LinkStatus {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, LinkStatus::Linked | LinkStatus::Skipped(_))
+    }
 }
 
-#[derive(PartialEq, Debug)]
-pub enum DotfilesOrigin {
-    Directory(FullPath),
-    Repository(String, FullPath),
+impl Display for LinkStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Linked => write!(f, "linked ✔️"),
+            Self::Missing => write!(f, "missing"),
+            Self::Blocked => write!(f, "blocked by a regular file"),
+            Self::WrongTarget(path) => write!(f, "points to {}", path.display()),
+            Self::Dangling(path) => write!(f, "dangling, points to {}", path.display()),
+            Self::OriginMissing => write!(f, "origin missing"),
+            Self::Skipped(reason) => write!(f, "skipped, {reason}"),
+            Self::Edited => write!(f, "rendered file edited by hand"),
+            Self::Outdated => write!(f, "outdated, link again"),
+        }
+    }
+}
+
+impl ProgramOptions {
+    /// Path the symlink points to, the rendered file for templates
+    pub fn source_path(&self) -> &Path {
+        match &self.template {
+            Some(template) => &template.output,
+            None => self.origin_path.as_path(),
+        }
+    }
+
+    pub fn hook(&self, kind: HookKind) -> Option<&Hook> {
+        self.hooks.iter().find(|hook| hook.kind == kind)
+    }
+
+    /// Runs the `kind` hook of the program if it has one
+    pub fn run_hook(&self, kind: HookKind) -> Result<()> {
+        match self.hook(kind) {
+            Some(hook) => hook.run().map_err(|e| anyhow!("{}: {e}", self.origin_path)),
+            None => Ok(()),
+        }
+    }
+
+    /// Why this program is not linked on the current machine
+    pub fn skip_reason(&self) -> Option<String> {
+        self.conditions.unmet(Machine::current())
+    }
+
+    pub fn link_status(&self) -> LinkStatus {
+        if let Some(reason) = self.skip_reason() {
+            return LinkStatus::Skipped(reason);
+        }
+
+        if !self.origin_path.as_ref().exists() {
+            return LinkStatus::OriginMissing;
+        }
+
+        let target = self.target_path.as_path();
+        if fs::symlink_metadata(target).is_err() {
+            return LinkStatus::Missing;
+        }
+
+        let source = self.source_path();
+        match fs::read_link(target) {
+            Ok(points_to) if self.mode.is_symlink() && points_to == source => LinkStatus::Linked,
+            Ok(points_to) if target.exists() => LinkStatus::WrongTarget(points_to),
+            Ok(points_to) => LinkStatus::Dangling(points_to),
+            Err(_) if self.mode == LinkMode::Hardlink && same_file(target, source) => {
+                LinkStatus::Linked
+            }
+            Err(_) if self.mode == LinkMode::Copy && same_content(target, source) => {
+                LinkStatus::Linked
+            }
+            Err(_) => LinkStatus::Blocked,
+        }
+    }
+
+    /// Like `link_status`, but a copy that mage made and nobody changed since is
+    /// `Outdated` instead of `Blocked` when the origin changed
+    pub fn recorded_status(&self, state: &State) -> LinkStatus {
+        match self.link_status() {
+            LinkStatus::Blocked
+                if state
+                    .link(self.target_path.as_path())
+                    .is_some_and(|link| link.mode == LinkMode::Copy && link.is_linked()) =>
+            {
+                LinkStatus::Outdated
+            }
+            status => status,
+        }
+    }
 }
 
-fn magefile(path: PathBuf) -> anyhow::Result<Table> {
-    let magefile = fs::read_to_string(path)?;
-    let thing: Table =
-        toml::from_str(&magefile).map_err(|e| anyhow!("Failed to parse magefile:\n{e}"))?;
+fn same_content(a: &Path, b: &Path) -> bool {
+    match (fs::read(a), fs::read(b)) {
+        (Ok(a), Ok(b)) => hash(a) == hash(b),
+        _ => false,
+    }
+}
 
-    Ok(thing)
+fn magefile(path: PathBuf) -> Result<Magefile, MageError> {
+    let magefile =
+        fs::read_to_string(&path).map_err(|e| MageError::io(e, &path, "read magefile"))?;
+    toml::from_str(&magefile).map_err(|error| match magefile::reserved_entry(&magefile) {
+        Some(key) => MageError::InvalidEntry {
+            key: key.into(),
+            message: format!(
+                "the key is reserved for a magefile section, use \"./{key}\" for a file with this name"
+            ),
+        },
+        None => MageError::MagefileParse { path, error },
+    })
 }
 
-pub(crate) fn find_magefile<P: Into<PathBuf>>(path: P) -> anyhow::Result<Table> {
-    let dir = fs::read_dir(path.into())?;
+pub(crate) fn find_magefile<P: Into<PathBuf>>(path: P) -> Result<Magefile, MageError> {
+    let path = path.into();
+    let read_error = |e| MageError::io(e, &path, format!("read {}", path.display()));
+    let dir = fs::read_dir(&path).map_err(read_error)?;
     for entry in dir {
-        let entry = entry?;
+        let entry = entry.map_err(read_error)?;
         let filename = entry
             .file_name()
             .to_str()
@@ -75,30 +308,71 @@ pub(crate) fn find_magefile<P: Into<PathBuf>>(path: P) -> anyhow::Result<Table>
             .to_string();
 
         if filename.starts_with("magefile") {
-            return Ok(magefile(entry.path())?);
+            return magefile(entry.path());
         }
     }
 
-    Err(anyhow!("Magefile not found"))
+    Err(MageError::MagefileNotFound { dir: path })
+}
+
+/// Packages the magefile in `path` declares
+pub(crate) fn magefile_packages(path: &Path) -> anyhow::Result<Vec<Package>> {
+    let magefile = find_magefile(path)?;
+    Ok(declared_packages(&magefile))
+}
+
+/// Hooks of the whole magefile in `path`
+pub(crate) fn magefile_hooks(path: &Path) -> anyhow::Result<Vec<Hook>> {
+    let magefile = find_magefile(path)?;
+    Ok(Hook::magefile(&magefile.hooks, path))
 }
 
 pub(crate) fn ensure_repo_is_setup(origin: DotfilesOrigin) -> anyhow::Result<FullPath> {
     match origin {
-        DotfilesOrigin::Repository(url, path) => {
-            let target_path = FullPath::from(path);
-            if target_path.as_path().exists() {
-                return Ok(target_path);
+        DotfilesOrigin::Repository(url, path, reference) => {
+            if !path.as_path().exists() {
+                clone_repo(&url, path.to_str(), reference.as_deref())?;
+                return Ok(path);
             }
 
-            clone_repo(&url, target_path.to_str())?;
+            ensure_clone_of(&url, &path)?;
+            if let Some(reference) = reference {
+                let git = git::backend();
+                git.fetch(path.as_path())?;
+                git.checkout(path.as_path(), &reference)?;
+                record_checkout(&url, &path, Some(reference))?;
+            }
 
-            return Ok(target_path);
+            Ok(path)
         }
         DotfilesOrigin::Directory(dir) => Ok(dir),
     }
 }
 
-pub(crate) fn clone_repo<'a>(url: &str, path: &'a str) -> anyhow::Result<&'a str> {
+/// Fails unless `path` is a clone of `url`, so that another repository is never used
+/// in its place
+pub(crate) fn ensure_clone_of(url: &str, path: &FullPath) -> anyhow::Result<()> {
+    let hint = "pass --clone-dir to clone it somewhere else";
+    ensure!(
+        path.as_path().join(".git").exists(),
+        "{path} is not a git repository, {hint}"
+    );
+
+    let remote = git::backend().remote_url(path.as_path())?;
+    ensure!(
+        same_repository(&remote, url),
+        "{path} is a clone of {remote}, not {url}, {hint}"
+    );
+    Ok(())
+}
+
+/// Clones `url` to `path` and checks out `reference` if given, the checkout is
+/// recorded so that sync stays on the same ref
+pub(crate) fn clone_repo<'a>(
+    url: &str,
+    path: &'a str,
+    reference: Option<&str>,
+) -> anyhow::Result<&'a str> {
     let p = FullPath::from(path);
     ensure!(
         !p.as_ref().exists(),
@@ -107,82 +381,41 @@ pub(crate) fn clone_repo<'a>(url: &str, path: &'a str) -> anyhow::Result<&'a str
     );
 
     debug!(url = url, "cloning repo");
-
-    let success = std::process::Command::new("git")
-        .args(["clone", "--recurse-submodules", url, path])
-        .status()
-        .map(|s| s.success())?;
-
-    ensure!(success, "Failed to clone repository {url}");
-
+    let git = git::backend();
+    git.clone_repo(url, p.as_path())?;
+    if let Some(reference) = reference {
+        git.checkout(p.as_path(), reference)?;
+    }
     debug!("done");
 
-    Ok(path)
-}
-
-impl FromStr for DotfilesOrigin {
-    type Err = anyhow::Error;
-    fn from_str(s: &str) -> std::prelude::v1::Result<Self, Self::Err> {
-        let span = debug_span!("parse DotfilesOrigin", from = s);
-        let _guard = span.enter();
-
-        let default_location = "~/.mage".to_string();
-        let result = match s {
-            dir if is_dir(dir) => Ok(DotfilesOrigin::Directory(dir.into())),
-            url if is_valid_repo_url(url) => Ok(DotfilesOrigin::Repository(
-                url.to_string(),
-                default_location.into(),
-            )),
-            url if is_github_repo(url) => Ok(DotfilesOrigin::Repository(
-                full_repo_url(url),
-                default_location.into(),
-            )),
-            _ => Err(anyhow!("This url seems to be invalid: {s}")),
-        };
-
-        debug!(got = ?result);
+    record_checkout(url, &p, reference.map(ToString::to_string))?;
 
-        result
-    }
+    Ok(path)
 }
 
-fn is_valid_repo_url(s: &str) -> bool {
-    let regexes = vec![
-        Regex::new(r"git@github.com:[A-z-\d]+\/[A-z-\d_]+.git").unwrap(),
-        Regex::new(r"https://github.com/[A-z-\d]+\/[A-z-\d_]+.git").unwrap(),
-    ];
-    return regexes
-        .iter()
-        .map(|r| r.find(s).is_some_and(|m| m.len() == s.len()))
-        .any(|v| v);
+fn record_checkout(url: &str, path: &FullPath, reference: Option<String>) -> anyhow::Result<()> {
+    let mut state = State::load()?;
+    state.insert_checkout(Checkout {
+        path: path.as_path().to_path_buf(),
+        url: url.to_string(),
+        reference,
+    });
+    state.save()
 }
 
-fn is_github_repo(s: &str) -> bool {
-    if is_valid_repo_url(s) {
-        return true;
-    }
-
-    let path = PathBuf::from(s);
-
-    if path.exists() {
-        return false;
+/// Target of the `origin` of `entry`, the origin keeps its name in a `target_dir`
+fn target_of(entry: &Entry, origin: &Path) -> FullPath {
+    match (&entry.target_path, &entry.target_dir) {
+        (Some(target_path), _) => FullPath::from(target_path.clone()),
+        (None, target_dir) => {
+            let target_dir = FullPath::from(target_dir.clone().unwrap_or_default());
+            let name = origin.file_name().unwrap_or(origin.as_os_str());
+            FullPath::from(target_dir.path().join(name))
+        }
     }
-
-    let repo_re = Regex::new(r"[A-z-\d]+\/[A-z-\d_]+").expect("should be able to construct regex");
-
-    repo_re.find(s).is_some_and(|m| m.len() == s.len())
 }
 
-fn full_repo_url(s: &str) -> String {
-    format!("git@github.com:{s}.git") // Assume ssh
-}
-
-fn is_dir(s: &str) -> bool {
-    let t: FullPath = s.into();
-    t.as_ref().exists()
-}
-
-fn get_full_origin_path(base_path: &Path, path_in_magefile: &str) -> FullPath {
+fn get_full_origin_path(base_path: &Path, path_in_magefile: &Path) -> FullPath {
     let path = base_path.join(path_in_magefile);
     FullPath::from(path)
 }
@@ -191,6 +424,8 @@ fn get_full_origin_path(base_path: &Path, path_in_magefile: &str) -> FullPath {
 mod tests {
 
     use super::*;
+    use crate::{git::test_repo, util::test_context::Ctx};
+
     #[test]
     #[should_panic]
     fn invalid_magefile() {
@@ -201,36 +436,24 @@ mod tests {
     #[test]
     #[should_panic]
     fn does_not_clone_if_path_exists() {
-        clone_repo("empty", "examples/test-dotfiles").unwrap();
+        clone_repo("empty", "examples/test-dotfiles", None).unwrap();
     }
 
     #[test]
     #[ignore = "clones repo and requires internet connection"]
     fn test_clone_repo() {
         fs::remove_dir_all("/tmp/mage").unwrap_or_default();
-        clone_repo("https://github.com/ollivarila/brainfckr.git", "/tmp/mage").unwrap();
+        clone_repo(
+            "https://github.com/ollivarila/brainfckr.git",
+            "/tmp/mage",
+            None,
+        )
+        .unwrap();
         let dir_exists = PathBuf::from("/tmp/mage").exists();
         assert!(dir_exists);
         fs::remove_dir_all("/tmp/mage").unwrap_or_default();
     }
 
-    #[test]
-    fn dotfiles_origin_from_str() {
-        let df_origin: DotfilesOrigin = "/tmp".parse().unwrap();
-
-        assert_eq!(df_origin, DotfilesOrigin::Directory("/tmp".into()));
-
-        let df_origin: DotfilesOrigin = "https://github.com/test/repo.git".parse().unwrap();
-        let should_be = DotfilesOrigin::Repository(
-            "https://github.com/test/repo.git".to_string(),
-            "~/.mage".into(),
-        );
-        assert_eq!(df_origin, should_be);
-
-        let res: Result<DotfilesOrigin, anyhow::Error> = "asdf".parse();
-        assert!(res.is_err())
-    }
-
     struct Context {
         path: String,
     }
@@ -251,37 +474,199 @@ mod tests {
     }
 
     #[test]
-    fn test_is_not_github_repo() {
-        assert!(!is_github_repo("/tmp/test"));
-        assert!(is_github_repo("test/test"));
-        assert!(!is_github_repo("git@something"));
-        assert!(is_github_repo("git@github.com:test/test-repo.git"));
-        assert!(is_github_repo("https://github.com/test/test-repo.git"));
+    fn link_status_missing_and_linked() {
+        let ctx = Ctx::default();
+        assert_eq!(ctx.opts.link_status(), LinkStatus::Missing);
+
+        std::os::unix::fs::symlink(&ctx.opts.origin_path, &ctx.opts.target_path).unwrap();
+        assert_eq!(ctx.opts.link_status(), LinkStatus::Linked);
     }
 
     #[test]
-    fn test_dotfiles_origin_github_url() {
-        let origin: DotfilesOrigin = "test/test-repo".parse().unwrap();
-        let should_be = DotfilesOrigin::Repository(
-            "git@github.com:test/test-repo.git".into(),
-            "~/.mage".into(),
+    fn link_status_blocked() {
+        let ctx = Ctx::default();
+        fs::write(&ctx.opts.target_path, "").unwrap();
+
+        assert_eq!(ctx.opts.link_status(), LinkStatus::Blocked);
+    }
+
+    #[test]
+    fn link_status_wrong_target_and_dangling() {
+        let ctx = Ctx::default();
+        std::os::unix::fs::symlink("/tmp", &ctx.opts.target_path).unwrap();
+        assert_eq!(
+            ctx.opts.link_status(),
+            LinkStatus::WrongTarget(PathBuf::from("/tmp"))
+        );
+
+        fs::remove_file(&ctx.opts.target_path).unwrap();
+        std::os::unix::fs::symlink("/nonexistent", &ctx.opts.target_path).unwrap();
+        assert_eq!(
+            ctx.opts.link_status(),
+            LinkStatus::Dangling(PathBuf::from("/nonexistent"))
+        );
+    }
+
+    #[test]
+    fn link_status_origin_missing() {
+        let mut ctx = Ctx::default();
+        ctx.opts.origin_path = "/nonexistent".into();
+
+        assert_eq!(ctx.opts.link_status(), LinkStatus::OriginMissing);
+    }
+
+    #[test]
+    fn link_status_skipped() {
+        let mut ctx = Ctx::default();
+        ctx.opts.conditions.os = vec!["not-an-os".into()];
+
+        assert!(matches!(ctx.opts.link_status(), LinkStatus::Skipped(_)));
+    }
+
+    #[test]
+    fn generate_reads_conditions() {
+        let magefile: Magefile = toml::from_str(
+            r#"
+            ["example.config"]
+            target_path = "/tmp/example.config"
+            os = "linux"
+            "#,
+        )
+        .unwrap();
+        let programs = ProgramOptions::generate(magefile, "/dotfiles".into()).unwrap();
+
+        assert_eq!(programs[0].conditions.os, vec!["linux".to_string()]);
+    }
+
+    #[test]
+    fn generate_selects_profile() {
+        let magefile: Magefile = toml::from_str(
+            r#"
+            [".bashrc"]
+            target_path = "~/.bashrc"
+
+            ["nvim"]
+            target_path = "~/.config/nvim"
+
+            [profiles.minimal]
+            entries = [".bashrc"]
+            "#,
+        )
+        .unwrap();
+        let base_path = FullPath::from("/dotfiles");
+
+        let all = ProgramOptions::generate(magefile.clone(), base_path.clone()).unwrap();
+        assert_eq!(all.len(), 2);
+
+        let minimal =
+            ProgramOptions::generate_profile(magefile, base_path.clone(), Some("minimal")).unwrap();
+        assert_eq!(minimal.len(), 1);
+        assert_eq!(minimal[0].entry, ".bashrc");
+    }
+
+    #[test]
+    fn generate_links_contents() {
+        let dir = PathBuf::from("/tmp/mage-generate-contents");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        for file in ["nvim/init.lua", "nvim/lua/plugins.lua", "nvim/debug.log"] {
+            fs::create_dir_all(dir.join(file).parent().unwrap()).unwrap();
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let magefile: Magefile = toml::from_str(
+            r#"
+            ["nvim"]
+            target_path = "/home/user/.config/nvim"
+            link = "contents"
+            ignore = "*.log"
+
+            [profiles.editor]
+            entries = ["nvim"]
+            "#,
+        )
+        .unwrap();
+
+        let programs =
+            ProgramOptions::generate_profile(magefile, dir.clone().into(), Some("editor")).unwrap();
+        let targets = programs
+            .iter()
+            .map(|p| p.target_path.to_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            vec![
+                "/home/user/.config/nvim/init.lua",
+                "/home/user/.config/nvim/lua/plugins.lua"
+            ]
+        );
+        assert_eq!(programs[1].entry, "nvim/lua/plugins.lua");
+
+        let ignore_whole: Magefile =
+            toml::from_str("[\"nvim\"]\ntarget_path = \"/tmp\"\nignore = \"*.log\"\n").unwrap();
+        let err = ProgramOptions::generate(ignore_whole, dir.clone().into()).unwrap_err();
+        assert!(matches!(err, MageError::InvalidEntry { key, .. } if key == "nvim"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn generate_expands_patterns() {
+        let dir = PathBuf::from("/tmp/mage-generate-patterns");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        fs::create_dir_all(dir.join("bin")).unwrap();
+        fs::write(dir.join("bin/backup"), "").unwrap();
+        fs::write(dir.join("bin/deploy"), "").unwrap();
+        let magefile: Magefile = toml::from_str(
+            r#"
+            ["bin/*"]
+            target_dir = "/home/user/.local/bin"
+
+            ["bin/backup"]
+            target_dir = "/usr/local/bin"
+
+            ["scripts/*"]
+            target_dir = "/home/user/.local/bin"
+            "#,
+        )
+        .unwrap();
+
+        let programs = ProgramOptions::generate(magefile, dir.clone().into()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let programs = programs
+            .iter()
+            .map(|p| (p.entry.clone(), p.target_path.to_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            programs,
+            vec![
+                ("bin/backup".into(), "/home/user/.local/bin/backup"),
+                ("bin/deploy".into(), "/home/user/.local/bin/deploy"),
+                ("bin/backup".into(), "/usr/local/bin/backup"),
+            ]
         );
-        assert_eq!(origin, should_be);
     }
 
     #[test]
     fn repo_is_setup_when_path_exists() {
-        let path = "examples/test-dotfiles";
-        let origin = DotfilesOrigin::Repository("empty".to_string(), path.into());
+        let (remote, local) = test_repo::setup("repo-is-setup");
+        let url = format!("file://{}", remote.display());
+        let path = FullPath::from(local.clone());
+        let origin = DotfilesOrigin::Repository(url, path.clone(), None);
         let result = ensure_repo_is_setup(origin).unwrap();
-        assert_eq!(result, path.into());
+        assert_eq!(result, path);
+
+        let origin = DotfilesOrigin::Repository("user/other".into(), path.clone(), None);
+        let err = ensure_repo_is_setup(origin).unwrap_err();
+        assert!(err.to_string().contains("--clone-dir"));
+
+        let origin = DotfilesOrigin::Repository("empty".into(), "examples".into(), None);
+        assert!(ensure_repo_is_setup(origin).is_err());
+        fs::remove_dir_all(remote.parent().unwrap()).unwrap();
     }
 
     #[test]
     #[should_panic]
     fn ensure_repo_is_setup_errors_when_repo_does_not_exist() {
         let path = "/tmp/something".to_string();
-        let origin = DotfilesOrigin::Repository("empty".into(), path.into());
+        let origin = DotfilesOrigin::Repository("empty".into(), path.into(), None);
         ensure_repo_is_setup(origin).unwrap();
     }
 }
@@ -0,0 +1,121 @@
+use std::{collections::HashMap, fs, process::Command, sync::OnceLock};
+
+use crate::util::glob_match;
+
+/// Conditions in a magefile entry for the machines it is linked on.
+/// Empty conditions match every machine.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Conditions {
+    /// Glob patterns of allowed hostnames, e.g. `hosts = ["work-*"]`
+    pub hosts: Vec<String>,
+    /// Allowed operating systems, e.g. `os = "linux"`
+    pub os: Vec<String>,
+    /// Environment variables and the glob patterns their values must match,
+    /// e.g. `env = { DISPLAY = "*" }`
+    pub env: Vec<(String, String)>,
+}
+
+impl Conditions {
+    /// Returns the reason why `machine` doesn't satisfy the conditions
+    pub fn unmet(&self, machine: &Machine) -> Option<String> {
+        if !self.hosts.is_empty() && !self.hosts.iter().any(|h| glob_match(h, &machine.hostname)) {
+            return Some(format!("host {} not in {:?}", machine.hostname, self.hosts));
+        }
+
+        if !self.os.is_empty() && !self.os.contains(&machine.os) {
+            return Some(format!("os {} not in {:?}", machine.os, self.os));
+        }
+
+        for (key, pattern) in &self.env {
+            match machine.env.get(key) {
+                Some(value) if glob_match(pattern, value) => {}
+                Some(value) => return Some(format!("env {key}={value} does not match {pattern}")),
+                None => return Some(format!("env {key} is not set")),
+            }
+        }
+
+        None
+    }
+}
+
+/// Facts about the machine that conditions are matched against
+#[derive(Debug, Default)]
+pub struct Machine {
+    pub hostname: String,
+    pub os: String,
+    pub env: HashMap<String, String>,
+}
+
+impl Machine {
+    pub fn current() -> &'static Machine {
+        static MACHINE: OnceLock<Machine> = OnceLock::new();
+        MACHINE.get_or_init(|| Machine {
+            hostname: hostname(),
+            os: std::env::consts::OS.to_string(),
+            env: std::env::vars().collect(),
+        })
+    }
+}
+
+fn hostname() -> String {
+    if let Ok(name) = fs::read_to_string("/proc/sys/kernel/hostname") {
+        return name.trim().to_string();
+    }
+
+    Command::new("hostname")
+        .output()
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> Machine {
+        Machine {
+            hostname: "work-laptop".into(),
+            os: "linux".into(),
+            env: HashMap::from([("DISPLAY".into(), ":0".into())]),
+        }
+    }
+
+    #[test]
+    fn empty_conditions_match() {
+        assert_eq!(Conditions::default().unmet(&machine()), None);
+    }
+
+    #[test]
+    fn matching_conditions() {
+        let c = Conditions {
+            hosts: vec!["home-*".into(), "work-*".into()],
+            os: vec!["linux".into()],
+            env: vec![("DISPLAY".into(), "*".into())],
+        };
+        assert_eq!(c.unmet(&machine()), None);
+    }
+
+    #[test]
+    fn unmet_conditions() {
+        let c = Conditions {
+            hosts: vec!["server-*".into()],
+            ..Default::default()
+        };
+        assert!(c.unmet(&machine()).unwrap().contains("work-laptop"));
+
+        let c = Conditions {
+            os: vec!["macos".into()],
+            ..Default::default()
+        };
+        assert!(c.unmet(&machine()).unwrap().contains("os linux"));
+
+        let c = Conditions {
+            env: vec![("WAYLAND_DISPLAY".into(), "*".into())],
+            ..Default::default()
+        };
+        assert_eq!(
+            c.unmet(&machine()).unwrap(),
+            "env WAYLAND_DISPLAY is not set"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::magefile::is_pattern;
+use crate::util::glob_match;
+
+/// Paths in `dir` matching the glob `pattern`, relative to `dir`. Like in a shell
+/// `*` does not match `/` or a leading `.`
+pub fn matching(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![PathBuf::new()];
+    for component in Path::new(pattern).iter() {
+        let component = component.to_string_lossy();
+        if !is_pattern(&component) {
+            paths.iter_mut().for_each(|path| path.push(&*component));
+            continue;
+        }
+
+        let mut matches = vec![];
+        for path in paths.iter().filter(|path| dir.join(path).is_dir()) {
+            for entry in fs::read_dir(dir.join(path))? {
+                let name = entry?.file_name().to_string_lossy().to_string();
+                let hidden = name.starts_with('.') && !component.starts_with('.');
+                if !hidden && glob_match(&component, &name) {
+                    matches.push(path.join(name));
+                }
+            }
+        }
+        paths = matches;
+    }
+
+    paths.retain(|path| fs::symlink_metadata(dir.join(path)).is_ok());
+    paths.sort();
+    Ok(paths)
+}
+
+/// Files in `dir` and its subdirectories, relative to `dir`. Files and directories
+/// whose name or relative path match one of the `ignore` globs are left out.
+pub fn files(dir: &Path, ignore: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    collect(dir, Path::new(""), ignore, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn collect(dir: &Path, relative: &Path, ignore: &[String], files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir.join(relative))
+        .with_context(|| format!("read directory {}", dir.join(relative).display()))?;
+
+    for entry in entries {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        let ignored = ignore.iter().any(|pattern| {
+            glob_match(pattern, &entry.file_name().to_string_lossy())
+                || glob_match(pattern, &path.to_string_lossy())
+        });
+        if ignored {
+            continue;
+        }
+
+        // Symlinks in the dotfiles are linked like files
+        if entry.file_type()?.is_dir() {
+            collect(dir, &path, ignore, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_patterns() {
+        let dir = PathBuf::from("/tmp/mage-matching");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        for file in ["bin/a", "bin/b.sh", "bin/.hidden", "bin/lib/c", "other/d"] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let paths = |pattern| matching(&dir, pattern).unwrap();
+        assert_eq!(
+            paths("bin/*"),
+            vec![
+                PathBuf::from("bin/a"),
+                PathBuf::from("bin/b.sh"),
+                PathBuf::from("bin/lib")
+            ]
+        );
+        assert_eq!(paths("*/*.sh"), vec![PathBuf::from("bin/b.sh")]);
+        assert_eq!(paths("bin/.*"), vec![PathBuf::from("bin/.hidden")]);
+        assert_eq!(paths("*/lib/?"), vec![PathBuf::from("bin/lib/c")]);
+        assert!(paths("missing/*").is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_without_ignored() {
+        let dir = PathBuf::from("/tmp/mage-contents");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        for file in ["init.lua", "lua/plugins.lua", "lua/cache/x", "debug.log"] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let ignore = vec!["*.log".to_string(), "lua/cache".to_string()];
+        let files = files(&dir, &ignore).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            files,
+            vec![PathBuf::from("init.lua"), PathBuf::from("lua/plugins.lua")]
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::{ensure, Context, Result};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use tracing::debug;
+
+use super::magefile::Hooks;
+
+/// When a hook runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookKind {
+    PreLink,
+    PostLink,
+    PostClean,
+}
+
+impl Display for HookKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PreLink => write!(f, "pre_link"),
+            Self::PostLink => write!(f, "post_link"),
+            Self::PostClean => write!(f, "post_clean"),
+        }
+    }
+}
+
+/// Shell command from the magefile, with the environment it runs in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hook {
+    pub kind: HookKind,
+    pub command: String,
+    /// Dotfiles directory, the hook runs in it
+    dir: PathBuf,
+    /// Origin and target of the entry, `None` for the hooks of the whole magefile
+    entry: Option<(PathBuf, PathBuf)>,
+}
+
+impl Hook {
+    /// Hooks of the whole magefile in `dir`
+    pub fn magefile(hooks: &Hooks, dir: &Path) -> Vec<Hook> {
+        Self::all(hooks, dir, None)
+    }
+
+    /// Hooks of the entry linking `origin` to `target`
+    pub fn entry(hooks: &Hooks, dir: &Path, origin: &Path, target: &Path) -> Vec<Hook> {
+        Self::all(
+            hooks,
+            dir,
+            Some((origin.to_path_buf(), target.to_path_buf())),
+        )
+    }
+
+    fn all(hooks: &Hooks, dir: &Path, entry: Option<(PathBuf, PathBuf)>) -> Vec<Hook> {
+        [
+            (HookKind::PreLink, &hooks.pre_link),
+            (HookKind::PostLink, &hooks.post_link),
+            (HookKind::PostClean, &hooks.post_clean),
+        ]
+        .into_iter()
+        .filter_map(|(kind, command)| {
+            Some(Hook {
+                kind,
+                command: command.clone()?,
+                dir: dir.to_path_buf(),
+                entry: entry.clone(),
+            })
+        })
+        .collect()
+    }
+
+    /// Variables the hook gets besides the environment of mage
+    pub fn env(&self) -> Vec<(&'static str, &Path)> {
+        let mut env = vec![("MAGE_DOTFILES", self.dir.as_path())];
+        if let Some((origin, target)) = &self.entry {
+            env.push(("MAGE_ORIGIN", origin.as_path()));
+            env.push(("MAGE_TARGET", target.as_path()));
+        }
+        env
+    }
+
+    /// Runs the hook with `sh` in the dotfiles directory. The output is captured and
+    /// only shown when the hook fails
+    pub fn run(&self) -> Result<()> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("MAGE_HOOK", self.kind.to_string())
+            .envs(self.env())
+            .current_dir(&self.dir)
+            .output()
+            .with_context(|| format!("run {self}"))?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        debug!(hook = %self.kind, command = self.command, %stdout, %stderr, "ran");
+        ensure!(
+            output.status.success(),
+            "{self} failed with {}:\n{}{}",
+            output.status,
+            stdout,
+            stderr
+        );
+
+        Ok(())
+    }
+}
+
+impl Display for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} hook `{}`", self.kind, self.command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(command: &str) -> Hook {
+        let hooks = Hooks {
+            post_link: Some(command.into()),
+            ..Default::default()
+        };
+        Hook::entry(&hooks, Path::new("/tmp"), Path::new("/a"), Path::new("/b")).remove(0)
+    }
+
+    #[test]
+    fn runs_with_environment() {
+        let out = PathBuf::from("/tmp/mage-hook-env");
+        let hook = hook(&format!(
+            "echo \"$MAGE_HOOK $MAGE_DOTFILES $MAGE_ORIGIN $MAGE_TARGET $PWD\" > {}",
+            out.display()
+        ));
+
+        hook.run().unwrap();
+        let env = std::fs::read_to_string(&out).unwrap();
+        std::fs::remove_file(out).unwrap();
+        assert_eq!(env, "post_link /tmp /a /b /tmp\n");
+    }
+
+    #[test]
+    fn fails_with_output() {
+        let err = hook("echo broken; exit 3").run().unwrap_err().to_string();
+
+        assert!(err.starts_with("post_link hook `echo broken; exit 3` failed"));
+        assert!(err.ends_with("broken\n"));
+    }
+
+    #[test]
+    fn magefile_hooks() {
+        let hooks = Hooks {
+            pre_link: Some("true".into()),
+            post_clean: Some("false".into()),
+            ..Default::default()
+        };
+
+        let hooks = Hook::magefile(&hooks, Path::new("/dotfiles"));
+        assert_eq!(hooks.len(), 2);
+        assert_eq!(hooks[1].kind, HookKind::PostClean);
+        assert_eq!(
+            hooks[1].env(),
+            vec![("MAGE_DOTFILES", Path::new("/dotfiles"))]
+        );
+    }
+}
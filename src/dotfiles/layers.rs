@@ -0,0 +1,184 @@
+use anyhow::Result;
+use std::fmt::Display;
+
+use super::{DotfilesOrigin, ProgramOptions};
+use crate::config::Config;
+use crate::util::FullPath;
+
+/// Dotfiles from one origin, named if they are a source in the user config
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub name: Option<String>,
+    pub origin: DotfilesOrigin,
+}
+
+impl Layer {
+    pub fn path(&self) -> &FullPath {
+        self.origin.path()
+    }
+}
+
+impl Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{name}"),
+            None => write!(f, "{}", self.path()),
+        }
+    }
+}
+
+/// Only `directory` if it is given, a repository is cloned to `clone_dir` then.
+/// Otherwise the sources in `config` from the highest priority down, or the default
+/// directory without any
+pub fn layers(
+    directory: Option<&str>,
+    clone_dir: Option<&str>,
+    config: &Config,
+) -> Result<Vec<Layer>> {
+    let defaults = config.repo_defaults();
+    let sources = config.sources();
+    if directory.is_none() && !sources.is_empty() {
+        return sources
+            .iter()
+            .map(|source| {
+                Ok(Layer {
+                    name: Some(source.name.clone()),
+                    origin: source.parse_origin(&defaults)?,
+                })
+            })
+            .collect();
+    }
+
+    let directory = directory.map_or_else(|| config.directory(), ToString::to_string);
+    let origin = DotfilesOrigin::parse(&directory, &defaults)?;
+    let origin = match clone_dir {
+        Some(dir) => origin.with_clone_dir(dir)?,
+        None => origin,
+    };
+    Ok(vec![Layer { name: None, origin }])
+}
+
+/// A target that is linked by several layers
+#[derive(Debug, PartialEq)]
+pub struct Conflict {
+    pub target: FullPath,
+    /// Layer whose entry is linked
+    pub winner: String,
+    pub overridden: Vec<String>,
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} from {} overrides {}",
+            self.target,
+            self.winner,
+            self.overridden.join(", ")
+        )
+    }
+}
+
+/// Entries of all layers with the index of their layer. A target linked by several
+/// layers goes to the first one of them, `layers` are in priority order
+pub fn merge(
+    layers: &[Layer],
+    programs: Vec<Vec<ProgramOptions>>,
+) -> (Vec<(usize, ProgramOptions)>, Vec<Conflict>) {
+    let mut merged: Vec<(usize, ProgramOptions)> = vec![];
+    let mut conflicts: Vec<Conflict> = vec![];
+
+    for (i, programs) in programs.into_iter().enumerate() {
+        for program in programs {
+            let target = &program.target_path;
+            let Some((winner, _)) = merged
+                .iter()
+                .find(|(j, p)| *j != i && p.target_path == *target)
+            else {
+                merged.push((i, program));
+                continue;
+            };
+
+            match conflicts.iter_mut().find(|c| c.target == *target) {
+                Some(conflict) => conflict.overridden.push(layers[i].to_string()),
+                None => conflicts.push(Conflict {
+                    target: target.clone(),
+                    winner: layers[*winner].to_string(),
+                    overridden: vec![layers[i].to_string()],
+                }),
+            }
+        }
+    }
+
+    (merged, conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_context::Ctx;
+
+    fn layer(name: &str) -> Layer {
+        Layer {
+            name: Some(name.into()),
+            origin: DotfilesOrigin::Directory("/tmp".into()),
+        }
+    }
+
+    fn program(origin: &str, target: &str) -> ProgramOptions {
+        let mut program = Ctx::default().opts.clone();
+        program.origin_path = origin.into();
+        program.target_path = target.into();
+        program
+    }
+
+    #[test]
+    fn higher_priority_wins() {
+        let layers = [layer("personal"), layer("team"), layer("company")];
+        let programs = vec![
+            vec![program("/personal/.bashrc", "/tmp/.bashrc")],
+            vec![
+                program("/team/.bashrc", "/tmp/.bashrc"),
+                program("/team/.vimrc", "/tmp/.vimrc"),
+            ],
+            vec![program("/company/.bashrc", "/tmp/.bashrc")],
+        ];
+
+        let (merged, conflicts) = merge(&layers, programs);
+
+        let origins = merged
+            .iter()
+            .map(|(i, p)| (*i, p.origin_path.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            origins,
+            vec![
+                (0, "/personal/.bashrc".to_string()),
+                (1, "/team/.vimrc".to_string())
+            ]
+        );
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            conflicts[0].to_string(),
+            "/tmp/.bashrc from personal overrides team, company"
+        );
+    }
+
+    #[test]
+    fn directory_or_sources() {
+        let config: Config = r#"
+            [[source]]
+            name = "personal"
+            origin = "/tmp"
+        "#
+        .parse()
+        .unwrap();
+
+        let from_config = layers(None, None, &config).unwrap();
+        assert_eq!(from_config, vec![layer("personal")]);
+
+        let directory = layers(Some("/tmp"), None, &config).unwrap();
+        assert_eq!(directory[0].name, None);
+        assert_eq!(directory[0].to_string(), "/tmp");
+    }
+}
@@ -0,0 +1,450 @@
+use serde::{
+    de::{DeserializeSeed, Error, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use std::collections::BTreeMap;
+
+/// Key of the profiles section in the magefile, e.g. `[profiles.minimal]`
+pub const PROFILES_KEY: &str = "profiles";
+/// Key of the template variables, e.g. `[variables]`
+pub const VARIABLES_KEY: &str = "variables";
+/// Key of the per-host template variables, e.g. `[host_variables."work-*"]`
+pub const HOST_VARIABLES_KEY: &str = "host_variables";
+/// Key of the hooks around a whole run, e.g. `[hooks]`
+pub const HOOKS_KEY: &str = "hooks";
+/// Key of the packages the dotfiles need, e.g. `[packages.neovim]`
+pub const PACKAGES_KEY: &str = "packages";
+
+/// Top level keys of the magefile sections, a file with one of these names needs a
+/// key like `./packages` to be an entry
+pub const RESERVED_KEYS: [&str; 5] = [
+    PROFILES_KEY,
+    VARIABLES_KEY,
+    HOST_VARIABLES_KEY,
+    HOOKS_KEY,
+    PACKAGES_KEY,
+];
+
+/// Typed contents of a magefile.
+///
+/// Every top level table is an entry keyed by its path in the dotfiles, except for
+/// [`PROFILES_KEY`], [`VARIABLES_KEY`], [`HOST_VARIABLES_KEY`], [`HOOKS_KEY`] and
+/// [`PACKAGES_KEY`].
+#[derive(Debug, Clone, Default)]
+pub struct Magefile {
+    pub entries: BTreeMap<String, Entry>,
+    pub profiles: BTreeMap<String, Profile>,
+    pub variables: BTreeMap<String, String>,
+    /// Hostname glob patterns and the variables they override
+    pub host_variables: BTreeMap<String, BTreeMap<String, String>>,
+    /// Hooks that run once before and after linking or cleaning
+    pub hooks: Hooks,
+    pub packages: BTreeMap<String, PackageSpec>,
+}
+
+/// One entry in the magefile
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    /// Target path for the symlink, `~` is expanded
+    #[serde(default)]
+    pub target_path: Option<String>,
+    /// Directory the origin is linked into under its own name, needed by patterns
+    #[serde(default)]
+    pub target_dir: Option<String>,
+    /// Glob patterns of allowed hostnames
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub hosts: Vec<String>,
+    /// Allowed operating systems
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub os: Vec<String>,
+    /// Environment variables and glob patterns their values must match
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Render the origin with the magefile variables before linking
+    #[serde(default)]
+    pub template: bool,
+    /// How the target is created
+    #[serde(default)]
+    pub mode: LinkMode,
+    /// Whether a directory is linked as a whole or file by file
+    #[serde(default)]
+    pub link: LinkScope,
+    /// Glob patterns of the files that are not linked with `link = "contents"`
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub ignore: Vec<String>,
+    /// Shell command that runs before the entry is linked
+    pub pre_link: Option<String>,
+    /// Shell command that runs after the entry is linked
+    pub post_link: Option<String>,
+    /// Shell command that runs after the entry is cleaned
+    pub post_clean: Option<String>,
+    /// Packages the entry needs, described in the packages section or named like
+    /// their binary
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub requires: Vec<String>,
+}
+
+impl Entry {
+    pub fn hooks(&self) -> Hooks {
+        Hooks {
+            pre_link: self.pre_link.clone(),
+            post_link: self.post_link.clone(),
+            post_clean: self.post_clean.clone(),
+        }
+    }
+
+    /// Checks that the entry with `key` has exactly one kind of target
+    fn validate(&self, key: &str) -> Result<(), String> {
+        match (&self.target_path, &self.target_dir) {
+            (Some(_), Some(_)) => Err(format!("{key}: set target_path or target_dir, not both")),
+            (None, None) => Err(format!("{key}: missing target_path or target_dir")),
+            (Some(_), None) if is_pattern(key) => {
+                Err(format!("{key} is a pattern, it needs a target_dir"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reserved key of the magefile `content` that is written like an entry, with a target
+pub fn reserved_entry(content: &str) -> Option<&'static str> {
+    let table: toml::Table = toml::from_str(content).ok()?;
+    RESERVED_KEYS.into_iter().find(|key| {
+        table
+            .get(*key)
+            .and_then(toml::Value::as_table)
+            .is_some_and(|section| {
+                section.contains_key("target_path") || section.contains_key("target_dir")
+            })
+    })
+}
+
+/// Whether the entry key is a glob pattern like `bin/*`
+pub fn is_pattern(key: &str) -> bool {
+    key.contains(['*', '?'])
+}
+
+/// What of an entry is linked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkScope {
+    /// The file or directory itself
+    #[default]
+    Whole,
+    /// Every file in the directory, in real directories mirroring the origin
+    Contents,
+}
+
+/// How an entry is put at its target path
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    #[default]
+    Symlink,
+    /// For programs that don't follow symlinks or replace them on save
+    Copy,
+    Hardlink,
+}
+
+impl LinkMode {
+    pub fn is_symlink(&self) -> bool {
+        *self == LinkMode::Symlink
+    }
+}
+
+/// Shell commands that run around linking and cleaning
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    pub pre_link: Option<String>,
+    pub post_link: Option<String>,
+    pub post_clean: Option<String>,
+}
+
+/// A package in the packages section, its name can differ between package managers
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageSpec {
+    /// Binary that is on `PATH` once the package is installed, the package name by default
+    pub bin: Option<String>,
+    pub apt: Option<String>,
+    pub dnf: Option<String>,
+    pub pacman: Option<String>,
+    pub brew: Option<String>,
+    pub cargo: Option<String>,
+}
+
+/// Named subset of the entries
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Keys of the entries in this profile
+    #[serde(default)]
+    pub entries: Vec<String>,
+    /// Other profiles whose entries are included
+    #[serde(default)]
+    pub include: Vec<String>,
+}
+
+impl<'de> Deserialize<'de> for Magefile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(MagefileVisitor)
+    }
+}
+
+/// Deserializes the entries one by one so that errors keep the location of the entry
+struct MagefileVisitor;
+
+impl<'de> Visitor<'de> for MagefileVisitor {
+    type Value = Magefile;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a table of magefile entries")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut magefile = Magefile::default();
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                PROFILES_KEY => magefile.profiles = map.next_value()?,
+                VARIABLES_KEY => magefile.variables = map.next_value()?,
+                HOST_VARIABLES_KEY => magefile.host_variables = map.next_value()?,
+                HOOKS_KEY => magefile.hooks = map.next_value()?,
+                PACKAGES_KEY => magefile.packages = map.next_value()?,
+                _ => {
+                    let entry = map.next_value_seed(EntrySeed(&key))?;
+                    magefile.entries.insert(key, entry);
+                }
+            }
+        }
+
+        Ok(magefile)
+    }
+}
+
+/// Deserializes the entry with its key, so that the validation errors have the
+/// location of the entry too
+struct EntrySeed<'a>(&'a str);
+
+impl<'de> DeserializeSeed<'de> for EntrySeed<'_> {
+    type Value = Entry;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Entry, D::Error> {
+        let entry = Entry::deserialize(deserializer)?;
+        entry.validate(self.0).map_err(D::Error::custom)?;
+        Ok(entry)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrList {
+    String(String),
+    List(Vec<String>),
+}
+
+fn string_or_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(match StringOrList::deserialize(deserializer)? {
+        StringOrList::String(s) => vec![s],
+        StringOrList::List(list) => list,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<Magefile, String> {
+        toml::from_str(s).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn parses_entries_and_profiles() {
+        let magefile = parse(
+            r#"
+            [".bashrc"]
+            target_path = "~/.bashrc"
+            os = "linux"
+
+            ["nvim"]
+            target_path = "~/.config/nvim"
+            hosts = ["work-*"]
+            env = { DISPLAY = "*" }
+
+            [profiles.minimal]
+            entries = [".bashrc"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(magefile.entries.len(), 2);
+        assert_eq!(magefile.entries[".bashrc"].os, vec!["linux"]);
+        assert_eq!(magefile.entries["nvim"].hosts, vec!["work-*"]);
+        assert_eq!(magefile.entries["nvim"].env["DISPLAY"], "*");
+        assert_eq!(magefile.profiles["minimal"].entries, vec![".bashrc"]);
+    }
+
+    #[test]
+    fn parses_variables() {
+        let magefile = parse(
+            r#"
+            [variables]
+            email = "me@home"
+
+            [host_variables."work-*"]
+            email = "me@work"
+
+            [".gitconfig"]
+            target_path = "~/.gitconfig"
+            template = true
+            "#,
+        )
+        .unwrap();
+
+        assert!(magefile.entries[".gitconfig"].template);
+        assert_eq!(magefile.entries[".gitconfig"].mode, LinkMode::Symlink);
+        assert_eq!(magefile.variables["email"], "me@home");
+        assert_eq!(magefile.host_variables["work-*"]["email"], "me@work");
+    }
+
+    #[test]
+    fn reserved_keys_are_not_entries() {
+        let content = "[\"packages\"]\ntarget_path = \"~/packages\"\n";
+        assert!(parse(content).is_err());
+        assert_eq!(reserved_entry(content), Some(PACKAGES_KEY));
+        assert_eq!(reserved_entry("[packages.ripgrep]\nbin = \"rg\"\n"), None);
+
+        let magefile = parse("[\"./packages\"]\ntarget_path = \"~/packages\"\n").unwrap();
+        assert!(magefile.entries.contains_key("./packages"));
+    }
+
+    #[test]
+    fn parses_mode() {
+        let magefile =
+            parse("[\".ssh/config\"]\ntarget_path = \"~/.ssh/config\"\nmode = \"copy\"\n");
+        assert_eq!(
+            magefile.unwrap().entries[".ssh/config"].mode,
+            LinkMode::Copy
+        );
+
+        let err = parse("[\"a\"]\ntarget_path = \"a\"\nmode = \"move\"\n").unwrap_err();
+        assert!(err.contains("unknown variant `move`"));
+    }
+
+    #[test]
+    fn parses_link_scope() {
+        let magefile = parse(
+            r#"
+            ["nvim"]
+            target_path = "~/.config/nvim"
+            link = "contents"
+            ignore = ["*.log", "lazy-lock.json"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(magefile.entries["nvim"].link, LinkScope::Contents);
+        assert_eq!(
+            magefile.entries["nvim"].ignore,
+            vec!["*.log", "lazy-lock.json"]
+        );
+
+        let magefile = parse("[\"a\"]\ntarget_path = \"a\"\n").unwrap();
+        assert_eq!(magefile.entries["a"].link, LinkScope::Whole);
+    }
+
+    #[test]
+    fn parses_target_dir() {
+        let magefile = parse("[\"bin/*\"]\ntarget_dir = \"~/.local/bin\"\n").unwrap();
+        assert_eq!(
+            magefile.entries["bin/*"].target_dir.as_deref(),
+            Some("~/.local/bin")
+        );
+
+        let err = parse("[\"bin/*\"]\ntarget_path = \"~/.local/bin\"\n").unwrap_err();
+        assert!(err.contains("bin/* is a pattern, it needs a target_dir"));
+        let err = parse("[\"a\"]\ntarget_path = \"a\"\ntarget_dir = \"b\"\n").unwrap_err();
+        assert!(err.contains("not both"));
+    }
+
+    #[test]
+    fn parses_hooks() {
+        let magefile = parse(
+            r#"
+            [hooks]
+            post_link = "fc-cache -f"
+
+            ["nvim"]
+            target_path = "~/.config/nvim"
+            post_link = "nvim --headless +Lazy! sync +qa"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(magefile.hooks.post_link.as_deref(), Some("fc-cache -f"));
+        let hooks = magefile.entries["nvim"].hooks();
+        assert!(hooks.pre_link.is_none());
+        assert_eq!(
+            hooks.post_link.as_deref(),
+            Some("nvim --headless +Lazy! sync +qa")
+        );
+        assert!(parse("[hooks]\npost_sync = \"true\"\n").is_err());
+    }
+
+    #[test]
+    fn parses_packages() {
+        let magefile = parse(
+            r#"
+            [packages.neovim]
+            bin = "nvim"
+            apt = "neovim"
+
+            [packages.ripgrep]
+
+            ["nvim"]
+            target_path = "~/.config/nvim"
+            requires = ["neovim", "git"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(magefile.packages.len(), 2);
+        assert_eq!(magefile.packages["neovim"].bin.as_deref(), Some("nvim"));
+        assert_eq!(magefile.packages["ripgrep"], PackageSpec::default());
+        assert_eq!(magefile.entries["nvim"].requires, vec!["neovim", "git"]);
+        assert!(parse("[packages.neovim]\nnix = \"neovim\"\n").is_err());
+    }
+
+    #[test]
+    fn unknown_key_has_location() {
+        let err =
+            parse("[\".bashrc\"]\ntarget_path = \"~/.bashrc\"\ntarget = \"typo\"\n").unwrap_err();
+
+        assert!(err.contains("line 3, column 1"));
+        assert!(err.contains("unknown field `target`"));
+    }
+
+    #[test]
+    fn invalid_target_path_has_location() {
+        let err = parse("[\".bashrc\"]\ntarget_path = 1\n").unwrap_err();
+
+        assert!(err.contains("line 2, column 15"));
+        assert!(err.contains("expected a string"));
+    }
+
+    #[test]
+    fn missing_target_path_has_location() {
+        let err = parse("[\"a\"]\ntarget_path = \"a\"\n\n[\"b\"]\nos = \"linux\"\n").unwrap_err();
+
+        assert!(err.contains("line 4, column 1"));
+        assert!(err.contains("b: missing target_path or target_dir"));
+    }
+
+    #[test]
+    fn invalid_conditions() {
+        assert!(parse("[\"a\"]\ntarget_path = \"a\"\nos = 1\n").is_err());
+        assert!(parse("[\"a\"]\ntarget_path = \"a\"\nenv = { A = 1 }\n").is_err());
+    }
+}
@@ -0,0 +1,431 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::{fs, path::Path, str::FromStr, sync::OnceLock};
+use tracing::{debug, debug_span};
+
+use crate::config::DEFAULT_DIRECTORY;
+use crate::util::FullPath;
+
+/// Where the dotfiles come from
+#[derive(PartialEq, Debug, Clone)]
+pub enum DotfilesOrigin {
+    Directory(FullPath),
+    /// Url of the repository, the directory it is cloned to and the branch, tag or
+    /// commit to check out instead of the default branch
+    Repository(String, FullPath, Option<String>),
+}
+
+/// Protocol of the urls built from shorthands like `user/repo`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Ssh,
+    Https,
+}
+
+impl FromStr for Protocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ssh" => Ok(Protocol::Ssh),
+            "https" => Ok(Protocol::Https),
+            _ => bail!("Unknown protocol {s}, expected ssh or https"),
+        }
+    }
+}
+
+/// Host and protocol used for `user/repo` shorthands without a host, and the
+/// directory repositories are cloned to
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepoDefaults {
+    pub host: String,
+    pub protocol: Protocol,
+    pub directory: String,
+}
+
+impl Default for RepoDefaults {
+    fn default() -> Self {
+        Self {
+            host: "github.com".to_string(),
+            protocol: Protocol::Ssh,
+            directory: DEFAULT_DIRECTORY.to_string(),
+        }
+    }
+}
+
+/// Shorthand prefixes like `gitlab:user/repo` and the hosts they stand for
+const HOST_SHORTHANDS: [(&str, &str); 5] = [
+    ("github", "github.com"),
+    ("gitlab", "gitlab.com"),
+    ("codeberg", "codeberg.org"),
+    ("bitbucket", "bitbucket.org"),
+    ("sr.ht", "git.sr.ht"),
+];
+
+impl DotfilesOrigin {
+    /// Parses a local directory, a repository url or a shorthand like `user/repo`,
+    /// `gitlab:user/repo` or `sr.ht:~user/repo`. Repositories can be followed by
+    /// `#ref` to check out a branch, tag or commit, e.g. `user/repo#v1.2.0`
+    pub fn parse(s: &str, defaults: &RepoDefaults) -> Result<Self> {
+        let span = debug_span!("parse DotfilesOrigin", from = s);
+        let _guard = span.enter();
+
+        let (origin, reference) = split_ref(s)?;
+        let repository = |url| {
+            DotfilesOrigin::Repository(url, defaults.directory.as_str().into(), reference.clone())
+        };
+        let path = FullPath::from(origin);
+        let result = if is_bare_repo(path.as_path()) {
+            // A relative path would be read as the host of the url
+            let path = fs::canonicalize(&path).context(format!("resolve {path}"))?;
+            Ok(repository(format!("file://{}", path.display())))
+        } else if path.as_path().exists() {
+            ensure!(
+                reference.is_none(),
+                "A ref can only be checked out from a repository: {s}"
+            );
+            Ok(DotfilesOrigin::Directory(path))
+        } else {
+            repo_url(origin, defaults)
+                .map(repository)
+                .ok_or_else(|| anyhow!("This url seems to be invalid: {s}"))
+        };
+
+        debug!(got = ?result);
+
+        result
+    }
+
+    /// Directory the dotfiles are in, or cloned to
+    pub fn path(&self) -> &FullPath {
+        match self {
+            DotfilesOrigin::Directory(path) | DotfilesOrigin::Repository(_, path, _) => path,
+        }
+    }
+
+    /// Clones a repository to `dir` instead of the default location
+    pub fn with_clone_dir(self, dir: &str) -> Result<Self> {
+        match self {
+            DotfilesOrigin::Repository(url, _, reference) => {
+                Ok(DotfilesOrigin::Repository(url, dir.into(), reference))
+            }
+            DotfilesOrigin::Directory(path) => {
+                bail!("{path} is a local directory, only repositories can be cloned to a directory")
+            }
+        }
+    }
+}
+
+/// Whether two urls point to the same repository, e.g. its ssh and https urls
+pub fn same_repository(a: &str, b: &str) -> bool {
+    normalize(a) == normalize(b)
+}
+
+/// `host/path` of a repository url without the user, port and `.git` suffix
+fn normalize(url: &str) -> String {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    static SCP: OnceLock<Regex> = OnceLock::new();
+    let full =
+        URL.get_or_init(|| Regex::new(r"^\w+://(?:[^@/]+@)?([^/:]*)(?::\d+)?(/.*)$").unwrap());
+    let scp = SCP.get_or_init(|| Regex::new(r"^(?:[^@/]+@)?([^/:]+):(.*)$").unwrap());
+
+    let url = url.trim_end_matches('/');
+    let url = url.strip_suffix(".git").unwrap_or(url);
+    if let Some(captures) = full.captures(url) {
+        format!("{}{}", &captures[1], &captures[2])
+    } else if let Some(captures) = scp.captures(url) {
+        format!("{}/{}", &captures[1], &captures[2])
+    } else {
+        url.to_string()
+    }
+}
+
+/// Splits `origin#ref` into the origin and the ref, unless `s` is an existing path
+fn split_ref(s: &str) -> Result<(&str, Option<String>)> {
+    static REF: OnceLock<Regex> = OnceLock::new();
+    let valid = REF.get_or_init(|| Regex::new(r"^[\w.][\w./-]*$").unwrap());
+
+    if FullPath::from(s).as_path().exists() {
+        return Ok((s, None));
+    }
+    match s.rsplit_once('#') {
+        Some((origin, reference)) => {
+            ensure!(
+                valid.is_match(reference),
+                "Invalid ref {reference:?} in {s}"
+            );
+            Ok((origin, Some(reference.to_string())))
+        }
+        None => Ok((s, None)),
+    }
+}
+
+/// Full url of the repository `s`, `None` if it's neither a url nor a shorthand
+fn repo_url(s: &str, defaults: &RepoDefaults) -> Option<String> {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    static SCP: OnceLock<Regex> = OnceLock::new();
+    static SHORTHAND: OnceLock<Regex> = OnceLock::new();
+
+    // e.g. https://host/user/repo.git, ssh://git@host:22/user/repo or file:///srv/repo.git
+    let url = URL.get_or_init(|| {
+        Regex::new(r"^(https?|ssh|git)://([\w.~-]+@)?[\w.-]+(:\d+)?/[^\s]+$|^file:///[^\s]+$")
+            .unwrap()
+    });
+    // e.g. git@host:user/repo.git
+    let scp = SCP.get_or_init(|| Regex::new(r"^[\w.-]+@[\w.-]+:[^\s/][^\s]*$").unwrap());
+    // e.g. user/repo, gitlab:user/repo or sr.ht:~user/repo
+    let shorthand =
+        SHORTHAND.get_or_init(|| Regex::new(r"^(?:([\w.-]+):)?(~?[\w.-]+/[\w.-]+)$").unwrap());
+
+    if url.is_match(s) || scp.is_match(s) {
+        return Some(s.to_string());
+    }
+
+    let captures = shorthand.captures(s)?;
+    let host = match captures.get(1) {
+        Some(prefix) => HOST_SHORTHANDS
+            .iter()
+            .find(|(shorthand, _)| *shorthand == prefix.as_str())
+            .map(|(_, host)| *host)?,
+        None => defaults.host.as_str(),
+    };
+
+    Some(shorthand_url(host, &captures[2], defaults.protocol))
+}
+
+fn shorthand_url(host: &str, path: &str, protocol: Protocol) -> String {
+    // sourcehut doesn't serve repositories with a .git suffix
+    let suffix = if host == "git.sr.ht" || path.ends_with(".git") {
+        ""
+    } else {
+        ".git"
+    };
+
+    match protocol {
+        Protocol::Ssh => format!("git@{host}:{path}{suffix}"),
+        Protocol::Https => format!("https://{host}/{path}{suffix}"),
+    }
+}
+
+/// Whether `path` is a bare git repository, they are cloned instead of used directly
+fn is_bare_repo(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    fn parse(s: &str) -> Result<DotfilesOrigin> {
+        DotfilesOrigin::parse(s, &RepoDefaults::default())
+    }
+
+    fn repository(url: &str) -> DotfilesOrigin {
+        DotfilesOrigin::Repository(url.to_string(), "~/.mage".into(), None)
+    }
+
+    #[test]
+    fn dotfiles_origin_parse() {
+        let df_origin = parse("/tmp").unwrap();
+
+        assert_eq!(df_origin, DotfilesOrigin::Directory("/tmp".into()));
+
+        let df_origin = parse("https://github.com/test/repo.git").unwrap();
+        let should_be = DotfilesOrigin::Repository(
+            "https://github.com/test/repo.git".to_string(),
+            "~/.mage".into(),
+            None,
+        );
+        assert_eq!(df_origin, should_be);
+
+        assert!(parse("asdf").is_err())
+    }
+
+    #[test]
+    fn test_is_not_github_repo() {
+        assert!(parse("/tmp/test").is_err());
+        assert_eq!(
+            parse("test/test").unwrap(),
+            repository("git@github.com:test/test.git")
+        );
+        assert!(parse("git@something").is_err());
+        assert_eq!(
+            parse("git@github.com:test/test-repo.git").unwrap(),
+            repository("git@github.com:test/test-repo.git")
+        );
+        assert_eq!(
+            parse("https://github.com/test/test-repo.git").unwrap(),
+            repository("https://github.com/test/test-repo.git")
+        );
+    }
+
+    #[test]
+    fn test_dotfiles_origin_github_url() {
+        let origin = parse("test/test-repo").unwrap();
+        let should_be = DotfilesOrigin::Repository(
+            "git@github.com:test/test-repo.git".into(),
+            "~/.mage".into(),
+            None,
+        );
+        assert_eq!(origin, should_be);
+    }
+
+    #[test]
+    fn urls_of_any_host() {
+        let urls = [
+            "https://gitlab.com/user/dotfiles.git",
+            "https://git.example.com:8443/group/sub/dotfiles",
+            "ssh://git@git.example.com:2222/user/dotfiles.git",
+            "git@codeberg.org:user/dotfiles.git",
+            "gitea@git.example.com:user/dotfiles",
+            "file:///srv/git/dotfiles.git",
+        ];
+
+        for url in urls {
+            assert_eq!(parse(url).unwrap(), repository(url), "{url}");
+        }
+    }
+
+    #[test]
+    fn shorthands() {
+        assert_eq!(
+            parse("gitlab:user/dotfiles").unwrap(),
+            repository("git@gitlab.com:user/dotfiles.git")
+        );
+        assert_eq!(
+            parse("sr.ht:~user/dotfiles").unwrap(),
+            repository("git@git.sr.ht:~user/dotfiles")
+        );
+        assert!(parse("unknown:user/dotfiles").is_err());
+    }
+
+    #[test]
+    fn configured_defaults() {
+        let defaults = RepoDefaults {
+            host: "git.example.com".into(),
+            protocol: Protocol::Https,
+            directory: "~/dotfiles".into(),
+        };
+
+        let repository =
+            |url: &str| DotfilesOrigin::Repository(url.to_string(), "~/dotfiles".into(), None);
+
+        let origin = DotfilesOrigin::parse("user/dotfiles", &defaults).unwrap();
+        assert_eq!(
+            origin,
+            repository("https://git.example.com/user/dotfiles.git")
+        );
+
+        let origin = DotfilesOrigin::parse("gitlab:user/dotfiles", &defaults).unwrap();
+        assert_eq!(origin, repository("https://gitlab.com/user/dotfiles.git"));
+    }
+
+    #[test]
+    fn rejects_invalid_characters() {
+        assert!(parse("us[er/re^po").is_err());
+        assert!(parse("https://github.com").is_err());
+        assert!(parse("git@host:").is_err());
+    }
+
+    #[test]
+    fn refs() {
+        let with_ref = |url: &str, reference: &str| {
+            DotfilesOrigin::Repository(url.into(), "~/.mage".into(), Some(reference.into()))
+        };
+
+        assert_eq!(
+            parse("user/dotfiles#v1.2.0").unwrap(),
+            with_ref("git@github.com:user/dotfiles.git", "v1.2.0")
+        );
+        assert_eq!(
+            parse("https://gitlab.com/user/dotfiles.git#feature/work").unwrap(),
+            with_ref("https://gitlab.com/user/dotfiles.git", "feature/work")
+        );
+        assert!(parse("user/dotfiles#").is_err());
+        assert!(parse("user/dotfiles#-f").is_err());
+        assert!(parse("/tmp#main").is_err());
+    }
+
+    #[test]
+    fn clone_dir() {
+        let origin = parse("user/dotfiles#main")
+            .unwrap()
+            .with_clone_dir("~/work-dotfiles")
+            .unwrap();
+        assert_eq!(
+            origin,
+            DotfilesOrigin::Repository(
+                "git@github.com:user/dotfiles.git".into(),
+                "~/work-dotfiles".into(),
+                Some("main".into())
+            )
+        );
+        assert!(parse("/tmp").unwrap().with_clone_dir("/dotfiles").is_err());
+    }
+
+    #[test]
+    fn same_repository_urls() {
+        let github = "git@github.com:user/dotfiles.git";
+        assert!(same_repository(github, "https://github.com/user/dotfiles"));
+        assert!(same_repository(
+            github,
+            "ssh://git@github.com:22/user/dotfiles.git/"
+        ));
+        assert!(same_repository(
+            "file:///srv/dotfiles.git",
+            "/srv/dotfiles.git"
+        ));
+        assert!(!same_repository(github, "git@gitlab.com:user/dotfiles.git"));
+        assert!(!same_repository(github, "git@github.com:user/other.git"));
+    }
+
+    #[test]
+    fn local_bare_repository() {
+        let path = "/tmp/mage-origin-bare.git";
+        fs::remove_dir_all(path).unwrap_or_default();
+        let status = Command::new("git")
+            .args(["init", "--bare", "-q", path])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert_eq!(
+            parse(path).unwrap(),
+            repository("file:///tmp/mage-origin-bare.git")
+        );
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn directory_with_hash() {
+        let path = "/tmp/mage-origin#work";
+        fs::create_dir_all(path).unwrap();
+        let origin = parse(path);
+        fs::remove_dir_all(path).unwrap();
+        assert_eq!(origin.unwrap(), DotfilesOrigin::Directory(path.into()));
+    }
+
+    #[test]
+    fn relative_bare_repository() {
+        let path = "target/mage-origin-relative.git";
+        fs::remove_dir_all(path).unwrap_or_default();
+        let status = Command::new("git")
+            .args(["init", "--bare", "-q", path])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let full = fs::canonicalize(path).unwrap();
+        let origin = parse(path);
+        fs::remove_dir_all(path).unwrap();
+        let url = format!("file://{}", full.display());
+        assert!(url.starts_with("file:///"));
+        assert_eq!(origin.unwrap(), repository(&url));
+        assert!(same_repository(&url, &url));
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+
+use super::magefile::{Magefile, PackageSpec};
+use crate::util::on_path;
+
+/// A package the dotfiles need
+#[derive(Debug, Clone, PartialEq)]
+pub struct Package {
+    pub name: String,
+    spec: PackageSpec,
+}
+
+impl Package {
+    /// Binary that shows whether the package is installed
+    pub fn bin(&self) -> &str {
+        self.spec.bin.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn is_installed(&self) -> bool {
+        on_path(self.bin())
+    }
+
+    /// Name of the package for the package manager `manager`, like `apt`
+    pub fn name_for(&self, manager: &str) -> &str {
+        let name = match manager {
+            "apt" => &self.spec.apt,
+            "dnf" => &self.spec.dnf,
+            "pacman" => &self.spec.pacman,
+            "brew" => &self.spec.brew,
+            "cargo" => &self.spec.cargo,
+            _ => &None,
+        };
+        name.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// Packages `names`, described by `specs` from the packages section if they're in it
+pub fn resolve(specs: &BTreeMap<String, PackageSpec>, names: &[String]) -> Vec<Package> {
+    names
+        .iter()
+        .map(|name| Package {
+            name: name.clone(),
+            spec: specs.get(name).cloned().unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Every package in the packages section of `magefile`
+pub fn declared_packages(magefile: &Magefile) -> Vec<Package> {
+    let names = magefile.packages.keys().cloned().collect::<Vec<_>>();
+    resolve(&magefile.packages, &names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_names() {
+        let magefile: Magefile = toml::from_str(
+            r#"
+            [packages.neovim]
+            bin = "nvim"
+            pacman = "neovim-git"
+            "#,
+        )
+        .unwrap();
+
+        let packages = resolve(&magefile.packages, &["neovim".into(), "sh".into()]);
+        assert_eq!(packages[0].bin(), "nvim");
+        assert_eq!(packages[0].name_for("pacman"), "neovim-git");
+        assert_eq!(packages[0].name_for("apt"), "neovim");
+        assert_eq!(packages[1].bin(), "sh");
+        assert!(packages[1].is_installed());
+        assert_eq!(declared_packages(&magefile), packages[..1]);
+    }
+}
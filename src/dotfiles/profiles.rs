@@ -0,0 +1,117 @@
+use anyhow::{anyhow, bail, ensure, Result};
+
+use super::Magefile;
+
+/// Keys of the magefile entries in profile `name`, including the entries of the
+/// profiles it includes.
+///
+/// ```toml
+/// [profiles.minimal]
+/// entries = [".bashrc"]
+///
+/// [profiles.desktop]
+/// entries = ["nvim"]
+/// include = ["minimal"]
+/// ```
+pub fn profile_entries(magefile: &Magefile, name: &str) -> Result<Vec<String>> {
+    let mut entries = vec![];
+    resolve(magefile, name, &mut vec![], &mut entries)?;
+
+    for entry in &entries {
+        ensure!(
+            magefile.entries.contains_key(entry),
+            "Profile {name} contains unknown entry {entry}"
+        );
+    }
+
+    Ok(entries)
+}
+
+fn resolve(
+    magefile: &Magefile,
+    name: &str,
+    visiting: &mut Vec<String>,
+    entries: &mut Vec<String>,
+) -> Result<()> {
+    if visiting.iter().any(|v| v == name) {
+        bail!("Profile {name} includes itself");
+    }
+
+    let profile = magefile
+        .profiles
+        .get(name)
+        .ok_or_else(|| anyhow!("Profile {name} not found"))?;
+
+    visiting.push(name.to_string());
+    for include in &profile.include {
+        resolve(magefile, include, visiting, entries)?;
+    }
+    visiting.pop();
+
+    for entry in &profile.entries {
+        if !entries.contains(entry) {
+            entries.push(entry.clone());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn magefile() -> Magefile {
+        toml::from_str(
+            r#"
+            [".bashrc"]
+            target_path = "~/.bashrc"
+
+            ["nvim"]
+            target_path = "~/.config/nvim"
+
+            [profiles.minimal]
+            entries = [".bashrc"]
+
+            [profiles.desktop]
+            entries = ["nvim"]
+            include = ["minimal"]
+
+            [profiles.broken]
+            entries = ["typo"]
+
+            [profiles.cycle]
+            include = ["cycle"]
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn entries_of_profile() {
+        let entries = profile_entries(&magefile(), "minimal").unwrap();
+        assert_eq!(entries, vec![".bashrc"]);
+    }
+
+    #[test]
+    fn entries_of_included_profiles() {
+        let entries = profile_entries(&magefile(), "desktop").unwrap();
+        assert_eq!(entries, vec![".bashrc", "nvim"]);
+    }
+
+    #[test]
+    fn invalid_profiles() {
+        let magefile = magefile();
+        let err = profile_entries(&magefile, "missing").unwrap_err();
+        assert_eq!(err.to_string(), "Profile missing not found");
+
+        let err = profile_entries(&magefile, "broken").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Profile broken contains unknown entry typo"
+        );
+
+        let err = profile_entries(&magefile, "cycle").unwrap_err();
+        assert_eq!(err.to_string(), "Profile cycle includes itself");
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{Machine, Magefile};
+use crate::util::{data_dir, glob_match, mirror_path};
+
+/// Origin file that is rendered with variables before it is linked
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    /// Where the rendered file is written, the symlink points here
+    pub output: PathBuf,
+    pub variables: BTreeMap<String, String>,
+}
+
+impl Template {
+    pub fn new(target: &Path, variables: BTreeMap<String, String>) -> Self {
+        Self {
+            output: rendered_path(target),
+            variables,
+        }
+    }
+
+    /// Renders the template at `origin`
+    pub fn render(&self, origin: &Path) -> Result<String> {
+        let template =
+            fs::read_to_string(origin).context(format!("read template {}", origin.display()))?;
+        render_str(&template, &self.variables)
+            .context(format!("render template {}", origin.display()))
+    }
+}
+
+/// Variables of the magefile for `machine`.
+/// Tables in `host_variables` whose pattern matches the hostname override `variables`.
+pub fn variables_for(magefile: &Magefile, machine: &Machine) -> BTreeMap<String, String> {
+    let mut variables = magefile.variables.clone();
+
+    magefile
+        .host_variables
+        .iter()
+        .filter(|(pattern, _)| glob_match(pattern, &machine.hostname))
+        .for_each(|(_, overrides)| variables.extend(overrides.clone()));
+
+    variables
+}
+
+/// Location of the rendered file for `target`, e.g. `~/.local/share/mage/rendered/home/user/.gitconfig`
+fn rendered_path(target: &Path) -> PathBuf {
+    mirror_path(target, &data_dir().join("rendered"))
+}
+
+/// Replaces every `{{ name }}` in `template` with the value of the variable
+fn render_str(template: &str, variables: &BTreeMap<String, String>) -> Result<String> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            bail!("unclosed {{{{ in template");
+        };
+
+        let name = after[..end].trim();
+        let Some(value) = variables.get(name) else {
+            bail!("undefined variable {name}");
+        };
+        result.push_str(value);
+        rest = &after[end + 2..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> BTreeMap<String, String> {
+        BTreeMap::from([("email".to_string(), "me@home".to_string())])
+    }
+
+    #[test]
+    fn renders_variables() {
+        let rendered = render_str("email = {{ email }}\nname={{email}}", &variables()).unwrap();
+        assert_eq!(rendered, "email = me@home\nname=me@home");
+    }
+
+    #[test]
+    fn invalid_templates() {
+        let err = render_str("{{ name }}", &variables()).unwrap_err();
+        assert_eq!(err.to_string(), "undefined variable name");
+
+        assert!(render_str("{{ email", &variables()).is_err());
+    }
+
+    #[test]
+    fn host_variables_override() {
+        let magefile: Magefile = toml::from_str(
+            r#"
+            [variables]
+            email = "me@home"
+            proxy = "none"
+
+            [host_variables."work-*"]
+            email = "me@work"
+            "#,
+        )
+        .unwrap();
+        let machine = Machine {
+            hostname: "work-laptop".into(),
+            ..Default::default()
+        };
+
+        let variables = variables_for(&magefile, &machine);
+        assert_eq!(variables["email"], "me@work");
+        assert_eq!(variables["proxy"], "none");
+    }
+}
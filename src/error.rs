@@ -0,0 +1,159 @@
+use std::{
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Errors mage tells apart, anything else is kept in `Other`
+#[derive(Debug, thiserror::Error)]
+pub enum MageError {
+    /// The config file or the environment overriding it is invalid
+    #[error("Invalid config in {origin}")]
+    Config {
+        origin: String,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("Magefile not found in {}", dir.display())]
+    MagefileNotFound { dir: PathBuf },
+    #[error("Failed to parse magefile {}:\n{error}", path.display())]
+    MagefileParse {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    /// An entry the magefile syntax allows but mage can't link
+    #[error("{key}: {message}")]
+    InvalidEntry { key: String, message: String },
+    #[error("{} does not exist", origin.display())]
+    MissingOrigin { origin: PathBuf },
+    /// Something that is not mage's is in the way of a link
+    #[error("{} ({status}), cannot link {}", target.display(), origin.display())]
+    TargetConflict {
+        target: PathBuf,
+        origin: PathBuf,
+        status: String,
+    },
+    #[error("git {command} failed in {}", dir.display())]
+    Git {
+        command: String,
+        dir: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("Permission denied for {}", path.display())]
+    Permission {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl MageError {
+    /// `error` from accessing `path`, with `context` unless it is a permission error
+    pub fn io(error: io::Error, path: &Path, context: impl Display) -> Self {
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => Self::Permission {
+                path: path.to_path_buf(),
+                source: error,
+            },
+            _ => Self::Other(anyhow::Error::new(error).context(context.to_string())),
+        }
+    }
+
+    /// What the user can do about the error
+    pub fn hint(&self) -> Option<String> {
+        let hint = match self {
+            Self::Config { .. } => "Fix the config, the README lists its settings".into(),
+            Self::MagefileNotFound { dir } => format!(
+                "Run `mage init` in {} or pass the dotfiles directory",
+                dir.display()
+            ),
+            Self::MagefileParse { .. } | Self::InvalidEntry { .. } => {
+                "Fix the magefile, the README lists the entry format".into()
+            }
+            Self::MissingOrigin { .. } => {
+                "Add it to the dotfiles or remove its entry from the magefile".into()
+            }
+            Self::TargetConflict { .. } => {
+                "Move it out of the way, or link with --force to back it up".into()
+            }
+            Self::Git { dir, .. } => format!(
+                "Check the repository with `git -C {} status`",
+                dir.display()
+            ),
+            Self::Permission { path, .. } => format!(
+                "Make sure you can write to {}",
+                path.parent().unwrap_or(path).display()
+            ),
+            Self::Other(_) => return None,
+        };
+        Some(hint)
+    }
+}
+
+/// The first `MageError` in the chain of `error`, also inside `Other`
+pub fn find(error: &anyhow::Error) -> Option<&MageError> {
+    error
+        .chain()
+        .find_map(|cause| match cause.downcast_ref::<MageError>()? {
+            MageError::Other(inner) => find(inner),
+            error => Some(error),
+        })
+}
+
+/// Hint for the first error in the chain of `error` that has one
+pub fn hint(error: &anyhow::Error) -> Option<String> {
+    find(error).and_then(MageError::hint)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    #[test]
+    fn finds_errors_in_the_chain() {
+        let missing = MageError::MissingOrigin {
+            origin: "/dotfiles/nvim".into(),
+        };
+        let error = Err::<(), _>(missing)
+            .context("Failed to link nvim")
+            .unwrap_err();
+        assert!(matches!(
+            find(&error),
+            Some(MageError::MissingOrigin { .. })
+        ));
+        assert_eq!(
+            hint(&error).unwrap(),
+            "Add it to the dotfiles or remove its entry from the magefile"
+        );
+
+        let other = anyhow::Error::from(MageError::Other(anyhow::Error::from(
+            MageError::MagefileNotFound { dir: "/tmp".into() },
+        )));
+        assert!(matches!(
+            find(&other),
+            Some(MageError::MagefileNotFound { .. })
+        ));
+        assert!(find(&anyhow!("Failed")).is_none());
+    }
+
+    #[test]
+    fn permission_errors() {
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        let error = MageError::io(denied, Path::new("/etc/mage/a"), "create link");
+        assert_eq!(error.to_string(), "Permission denied for /etc/mage/a");
+        assert_eq!(
+            error.hint().unwrap(),
+            "Make sure you can write to /etc/mage"
+        );
+
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        let error = MageError::io(missing, Path::new("/tmp/a"), "create link");
+        assert_eq!(error.to_string(), "create link");
+        assert!(error.hint().is_none());
+    }
+}
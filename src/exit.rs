@@ -0,0 +1,105 @@
+use std::{error::Error, fmt::Display, process::ExitCode};
+
+use crate::error::{find, MageError};
+
+/// Everything succeeded
+pub const SUCCESS: u8 = 0;
+/// Every entry failed, or the command failed for any other reason
+pub const FAILURE: u8 = 1;
+// 2 is used by clap for invalid arguments
+/// Some entries failed, the others were done
+pub const PARTIAL_FAILURE: u8 = 3;
+/// The config or a magefile is missing or invalid
+pub const CONFIG_ERROR: u8 = 4;
+/// A git operation failed
+pub const GIT_ERROR: u8 = 5;
+
+/// Some or all of the entries of a command failed
+#[derive(Debug, PartialEq)]
+pub struct PartialFailure {
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl Display for PartialFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} of {} entries failed", self.failed, self.total)
+    }
+}
+
+impl Error for PartialFailure {}
+
+/// Exit code for `error`, from the errors in its chain
+pub fn code(error: &anyhow::Error) -> u8 {
+    let partial = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<PartialFailure>());
+    if let Some(failure) = partial {
+        // Nothing was done if every entry failed
+        let all_failed = failure.failed == failure.total;
+        return if all_failed { FAILURE } else { PARTIAL_FAILURE };
+    }
+
+    match find(error) {
+        Some(
+            MageError::Config { .. }
+            | MageError::MagefileNotFound { .. }
+            | MageError::MagefileParse { .. }
+            | MageError::InvalidEntry { .. },
+        ) => CONFIG_ERROR,
+        Some(MageError::Git { .. }) => GIT_ERROR,
+        _ => FAILURE,
+    }
+}
+
+/// Exit code of the result of a command
+pub fn exit_code(result: &anyhow::Result<()>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::from(SUCCESS),
+        Err(e) => ExitCode::from(code(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(code(&anyhow!("Failed")), FAILURE);
+
+        let partial = anyhow::Error::new(PartialFailure {
+            failed: 1,
+            total: 3,
+        });
+        assert_eq!(partial.to_string(), "1 of 3 entries failed");
+        assert_eq!(code(&partial), PARTIAL_FAILURE);
+        let all = anyhow::Error::new(PartialFailure {
+            failed: 2,
+            total: 2,
+        });
+        assert_eq!(code(&all), FAILURE);
+
+        let magefile = anyhow::Error::from(MageError::MagefileNotFound { dir: "/tmp".into() });
+        assert_eq!(code(&magefile), CONFIG_ERROR);
+
+        let git = Err::<(), _>(MageError::Git {
+            command: "fetch".into(),
+            dir: "/dotfiles".into(),
+            source: anyhow!("fatal: not a git repository"),
+        })
+        .context("Failed to sync")
+        .unwrap_err();
+        assert_eq!(code(&git), GIT_ERROR);
+        assert_eq!(
+            git.chain().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "Failed to sync",
+                "git fetch failed in /dotfiles",
+                "fatal: not a git repository"
+            ]
+        );
+    }
+}
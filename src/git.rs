@@ -0,0 +1,164 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "native-git")]
+mod native;
+// Only used by the tests when built with the native backend
+#[cfg_attr(feature = "native-git", allow(dead_code))]
+mod process;
+
+/// Git operations mage needs on the dotfiles repository
+pub trait GitBackend {
+    /// Clones `url` into `dir` including its submodules
+    fn clone_repo(&self, url: &str, dir: &Path) -> Result<()>;
+    /// Checks out a branch, tag or commit. A branch that only exists on the remote
+    /// is created to track it, tags and commits detach the head
+    fn checkout(&self, dir: &Path, reference: &str) -> Result<()>;
+    /// Url of the origin remote
+    fn remote_url(&self, dir: &Path) -> Result<String>;
+    /// Name of the checked out branch, `None` if the head is detached
+    fn current_branch(&self, dir: &Path) -> Result<Option<String>>;
+    /// Fetches the upstream of the current branch, or origin when no branch is checked out
+    fn fetch(&self, dir: &Path) -> Result<()>;
+    /// Number of commits the current branch is ahead and behind its upstream
+    fn divergence(&self, dir: &Path) -> Result<(usize, usize)>;
+    /// Number of commits the local `branch` is ahead and behind its upstream, `None`
+    /// if there is no such local branch
+    fn branch_divergence(&self, dir: &Path, branch: &str) -> Result<Option<(usize, usize)>>;
+    /// Fast-forwards the current branch to its upstream
+    fn fast_forward(&self, dir: &Path) -> Result<()>;
+    /// Rebases the current branch onto its upstream, a conflict leaves the rebase in
+    /// progress for [`GitBackend::abort_rebase`]
+    fn rebase(&self, dir: &Path) -> Result<()>;
+    fn abort_rebase(&self, dir: &Path) -> Result<()>;
+    /// Stashes the uncommitted changes to tracked files
+    fn stash(&self, dir: &Path) -> Result<()>;
+    /// Applies and drops the latest stash, it is kept if applying fails
+    fn stash_pop(&self, dir: &Path) -> Result<()>;
+    /// Changed and untracked files in the working tree
+    fn status(&self, dir: &Path) -> Result<Vec<Change>>;
+    /// Commits every change including untracked files
+    fn commit_all(&self, dir: &Path, message: &str) -> Result<()>;
+    /// Pushes the current branch to its upstream
+    fn push(&self, dir: &Path) -> Result<()>;
+}
+
+/// The git backend mage was built with, the `native-git` feature uses libgit2
+/// instead of the `git` binary
+pub fn backend() -> Box<dyn GitBackend> {
+    #[cfg(feature = "native-git")]
+    return Box::new(native::NativeGit);
+    #[cfg(not(feature = "native-git"))]
+    return Box::new(process::ProcessGit);
+}
+
+/// A file in `git status`
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    /// Full path of the changed file
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ChangeKind {
+    Modified,
+    Untracked,
+    /// Unmerged after a conflicting merge, rebase or stash
+    Conflicted,
+}
+
+/// Whether any tracked file has uncommitted changes
+pub fn is_dirty(changes: &[Change]) -> bool {
+    changes.iter().any(|c| c.kind != ChangeKind::Untracked)
+}
+
+/// Files with unresolved conflicts, relative to `dir`
+pub fn conflicts(changes: &[Change], dir: &Path) -> Vec<String> {
+    changes
+        .iter()
+        .filter(|c| c.kind == ChangeKind::Conflicted)
+        .map(|c| {
+            c.path
+                .strip_prefix(dir)
+                .unwrap_or(&c.path)
+                .display()
+                .to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod test_repo {
+    use std::{fs, path::Path, path::PathBuf, process::Command};
+
+    /// Runs the git binary to set up repositories independent of the backend
+    pub fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    pub fn configure_user(dir: &Path) {
+        git(dir, &["config", "user.name", "mage"]);
+        git(dir, &["config", "user.email", "mage@localhost"]);
+    }
+
+    pub fn commit(dir: &Path, file: &str, content: &str) {
+        fs::write(dir.join(file), content).unwrap();
+        git(dir, &["add", "--all"]);
+        git(dir, &["commit", "-q", "-m", file]);
+    }
+
+    /// Creates `/tmp/mage-<name>/remote.git` and returns it with a clone that has
+    /// one pushed commit
+    pub fn setup(name: &str) -> (PathBuf, PathBuf) {
+        let root = PathBuf::from(format!("/tmp/mage-{name}"));
+        fs::remove_dir_all(&root).unwrap_or_default();
+        let remote = root.join("remote.git");
+        fs::create_dir_all(&remote).unwrap();
+        git(&remote, &["init", "--bare", "-q"]);
+
+        let local = clone(&root, "local");
+        commit(&local, "a", "a");
+        git(&local, &["push", "-q", "-u", "origin", "HEAD"]);
+
+        (remote, local)
+    }
+
+    /// Clones the remote of `setup` next to it
+    pub fn clone(root: &Path, name: &str) -> PathBuf {
+        git(root, &["clone", "-q", "remote.git", name]);
+        let dir = root.join(name);
+        configure_user(&dir);
+        dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dirty_and_conflicts() {
+        let dir = Path::new("/dotfiles");
+        let change = |path: &str, kind| Change {
+            path: dir.join(path),
+            kind,
+        };
+
+        let changes = vec![change("new", ChangeKind::Untracked)];
+        assert!(!is_dirty(&changes));
+
+        let changes = vec![
+            change("a", ChangeKind::Modified),
+            change("b", ChangeKind::Conflicted),
+        ];
+        assert!(is_dirty(&changes));
+        assert_eq!(conflicts(&changes, dir), vec!["b"]);
+    }
+}
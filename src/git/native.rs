@@ -0,0 +1,491 @@
+use anyhow::{anyhow, ensure, Context, Result};
+use git2::{
+    build::{CheckoutBuilder, RepoBuilder},
+    BranchType, Cred, ErrorCode, FetchOptions, IndexAddOption, ObjectType, Oid, PushOptions,
+    RemoteCallbacks, Repository, Status, StatusOptions, SubmoduleUpdateOptions,
+};
+use std::path::Path;
+use tracing::debug;
+
+use super::{Change, ChangeKind, GitBackend};
+use crate::error::MageError;
+
+/// Uses libgit2, works without the `git` binary
+pub struct NativeGit;
+
+impl GitBackend for NativeGit {
+    fn clone_repo(&self, url: &str, dir: &Path) -> Result<()> {
+        git("clone", dir, || {
+            let repo = RepoBuilder::new()
+                .fetch_options(fetch_options())
+                .clone(url, dir)
+                .context(format!("Failed to clone repository {url}"))?;
+            update_submodules(&repo)
+        })
+    }
+
+    fn checkout(&self, dir: &Path, reference: &str) -> Result<()> {
+        git("checkout", dir, || {
+            let repo = open(dir)?;
+            let local = match repo.find_branch(reference, BranchType::Local) {
+                Ok(branch) => Some(branch),
+                Err(_) => {
+                    match repo.find_branch(&format!("origin/{reference}"), BranchType::Remote) {
+                        Ok(remote) => {
+                            let commit = remote.get().peel_to_commit()?;
+                            let mut branch = repo.branch(reference, &commit, false)?;
+                            branch.set_upstream(Some(&format!("origin/{reference}")))?;
+                            Some(branch)
+                        }
+                        Err(_) => None,
+                    }
+                }
+            };
+
+            let failed = || format!("Failed to check out {reference}");
+            match local {
+                Some(branch) => {
+                    let branch = branch.into_reference();
+                    let name = branch.name().context("invalid branch name")?;
+                    repo.checkout_tree(
+                        &branch.peel(ObjectType::Commit)?,
+                        Some(CheckoutBuilder::new().safe()),
+                    )
+                    .with_context(failed)?;
+                    repo.set_head(name)?;
+                }
+                None => {
+                    let commit = repo
+                        .revparse_single(reference)
+                        .and_then(|object| object.peel_to_commit())
+                        .with_context(failed)?;
+                    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))
+                        .with_context(failed)?;
+                    repo.set_head_detached(commit.id())?;
+                }
+            }
+            debug!(reference, "checked out");
+            Ok(())
+        })
+    }
+
+    fn remote_url(&self, dir: &Path) -> Result<String> {
+        git("remote", dir, || {
+            let repo = open(dir)?;
+            let remote = repo.find_remote("origin")?;
+            remote
+                .url()
+                .map(ToString::to_string)
+                .context("invalid remote url")
+        })
+    }
+
+    fn current_branch(&self, dir: &Path) -> Result<Option<String>> {
+        git("branch", dir, || {
+            let repo = open(dir)?;
+            let head = repo.head()?;
+            Ok(head
+                .is_branch()
+                .then(|| head.shorthand().map(ToString::to_string))
+                .flatten())
+        })
+    }
+
+    fn fetch(&self, dir: &Path) -> Result<()> {
+        git("fetch", dir, || {
+            let repo = open(dir)?;
+            // A detached head at a pinned tag or commit fetches from origin
+            let remote = head_branch(&repo)
+                .and_then(|branch| Ok(repo.branch_upstream_remote(&branch)?))
+                .ok()
+                .and_then(|remote| remote.as_str().map(ToString::to_string))
+                .unwrap_or_else(|| "origin".to_string());
+            let remote = remote.as_str();
+
+            repo.find_remote(remote)?
+                .fetch::<&str>(&[], Some(&mut fetch_options()), None)
+                .context(format!("Failed to fetch from {remote}"))?;
+            Ok(())
+        })
+    }
+
+    fn divergence(&self, dir: &Path) -> Result<(usize, usize)> {
+        git("rev-list", dir, || {
+            let repo = open(dir)?;
+            let head = repo.head()?.peel_to_commit()?.id();
+            Ok(repo.graph_ahead_behind(head, upstream(&repo)?)?)
+        })
+    }
+
+    fn branch_divergence(&self, dir: &Path, branch: &str) -> Result<Option<(usize, usize)>> {
+        git("rev-list", dir, || {
+            let repo = open(dir)?;
+            let Ok(local) = repo.find_branch(branch, BranchType::Local) else {
+                return Ok(None);
+            };
+            let upstream = local
+                .upstream()
+                .context(format!("{branch} has no upstream"))?;
+            let head = local.get().peel_to_commit()?.id();
+            let upstream = upstream.get().peel_to_commit()?.id();
+            Ok(Some(repo.graph_ahead_behind(head, upstream)?))
+        })
+    }
+
+    fn fast_forward(&self, dir: &Path) -> Result<()> {
+        git("merge", dir, || {
+            let repo = open(dir)?;
+            let target = upstream(&repo)?;
+            let (analysis, _) = repo.merge_analysis(&[&repo.find_annotated_commit(target)?])?;
+            if analysis.is_up_to_date() {
+                return Ok(());
+            }
+            ensure!(
+                analysis.is_fast_forward(),
+                "Not possible to fast-forward to the upstream"
+            );
+
+            // A safe checkout fails instead of overwriting local changes
+            repo.checkout_tree(
+                &repo.find_object(target, None)?,
+                Some(CheckoutBuilder::new().safe()),
+            )
+            .context("Failed to check out the upstream")?;
+            repo.head()?.set_target(target, "mage: fast-forward")?;
+            debug!(%target, "fast-forward");
+            Ok(())
+        })
+    }
+
+    fn rebase(&self, dir: &Path) -> Result<()> {
+        git("rebase", dir, || {
+            let repo = open(dir)?;
+            let upstream = repo.find_annotated_commit(upstream(&repo)?)?;
+            let signature = repo.signature()?;
+            let mut rebase = repo.rebase(None, Some(&upstream), None, None)?;
+
+            while let Some(operation) = rebase.next() {
+                operation?;
+                // Leave the rebase in progress so that it can be aborted
+                ensure!(!repo.index()?.has_conflicts(), "Rebase has conflicts");
+
+                match rebase.commit(None, &signature, None) {
+                    Err(e) if e.code() == ErrorCode::Applied => debug!("already applied"),
+                    result => {
+                        result?;
+                    }
+                }
+            }
+
+            rebase.finish(Some(&signature))?;
+            Ok(())
+        })
+    }
+
+    fn abort_rebase(&self, dir: &Path) -> Result<()> {
+        git("rebase", dir, || {
+            let repo = open(dir)?;
+            repo.open_rebase(None)?.abort()?;
+            Ok(())
+        })
+    }
+
+    fn stash(&self, dir: &Path) -> Result<()> {
+        git("stash", dir, || {
+            let mut repo = open(dir)?;
+            let signature = repo.signature()?;
+            repo.stash_save(&signature, "mage sync", None)?;
+            Ok(())
+        })
+    }
+
+    fn stash_pop(&self, dir: &Path) -> Result<()> {
+        git("stash", dir, || {
+            let mut repo = open(dir)?;
+            repo.stash_apply(0, None)
+                .context("Stashed changes conflict with the working tree")?;
+
+            // libgit2 applies conflicting changes with conflict markers, keep the stash then
+            ensure!(
+                !repo.index()?.has_conflicts(),
+                "Stashed changes conflict with the pulled changes"
+            );
+            repo.stash_drop(0)?;
+            Ok(())
+        })
+    }
+
+    fn status(&self, dir: &Path) -> Result<Vec<Change>> {
+        git("status", dir, || {
+            let repo = open(dir)?;
+            let workdir = repo.workdir().context("repository has no working tree")?;
+            let mut options = StatusOptions::new();
+            options
+                .include_untracked(true)
+                .recurse_untracked_dirs(true)
+                .include_ignored(false);
+
+            let changes = repo
+                .statuses(Some(&mut options))?
+                .iter()
+                .filter_map(|entry| {
+                    let path = workdir.join(entry.path()?);
+                    let status = entry.status();
+                    let kind = if status.contains(Status::CONFLICTED) {
+                        ChangeKind::Conflicted
+                    } else if status == Status::WT_NEW {
+                        ChangeKind::Untracked
+                    } else {
+                        ChangeKind::Modified
+                    };
+                    Some(Change { path, kind })
+                })
+                .collect();
+
+            Ok(changes)
+        })
+    }
+
+    fn commit_all(&self, dir: &Path, message: &str) -> Result<()> {
+        git("commit", dir, || {
+            let repo = open(dir)?;
+            let mut index = repo.index()?;
+            index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+            // Removes the files that were deleted from the working tree
+            index.update_all(["*"], None)?;
+            index.write()?;
+
+            let tree = repo.find_tree(index.write_tree()?)?;
+            let parent = repo.head()?.peel_to_commit()?;
+            let signature = repo.signature()?;
+            repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &[&parent],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn push(&self, dir: &Path) -> Result<()> {
+        git("push", dir, || {
+            let repo = open(dir)?;
+            let branch = head_branch(&repo)?;
+            let short = branch.trim_start_matches("refs/heads/");
+            let remote = repo
+                .branch_upstream_remote(&branch)
+                .context("Current branch has no upstream")?;
+            let merge = repo
+                .config()?
+                .get_string(&format!("branch.{short}.merge"))?;
+
+            let mut callbacks = callbacks();
+            callbacks.push_update_reference(|reference, rejected| match rejected {
+                Some(reason) => Err(git2::Error::from_str(&format!(
+                    "{reference} was rejected: {reason}"
+                ))),
+                None => Ok(()),
+            });
+            let mut options = PushOptions::new();
+            options.remote_callbacks(callbacks);
+
+            repo.find_remote(remote.as_str().context("invalid remote name")?)?
+                .push(&[format!("{branch}:{merge}")], Some(&mut options))
+                .context("Failed to push")?;
+            Ok(())
+        })
+    }
+}
+
+/// Runs `f`, its failure is a failed git `command` in `dir` like with the `git` binary
+fn git<T>(command: &str, dir: &Path, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    f().map_err(|source| {
+        MageError::Git {
+            command: command.into(),
+            dir: dir.into(),
+            source,
+        }
+        .into()
+    })
+}
+
+fn open(dir: &Path) -> Result<Repository> {
+    Repository::open(dir).context(format!("{} is not a git repository", dir.display()))
+}
+
+/// Full name of the checked out branch, e.g. `refs/heads/main`
+fn head_branch(repo: &Repository) -> Result<String> {
+    let head = repo.head()?;
+    ensure!(head.is_branch(), "No branch is checked out");
+    head.name()
+        .map(ToString::to_string)
+        .ok_or_else(|| anyhow!("invalid branch name"))
+}
+
+/// Commit the upstream of the checked out branch points to
+fn upstream(repo: &Repository) -> Result<Oid> {
+    let upstream = repo
+        .branch_upstream_name(&head_branch(repo)?)
+        .context("Current branch has no upstream")?;
+    let upstream = upstream.as_str().context("invalid upstream name")?;
+    Ok(repo.refname_to_id(upstream)?)
+}
+
+/// Credentials from the ssh agent or the configured git credential helper
+fn callbacks<'a>() -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    let mut attempts = 0;
+    callbacks.credentials(move |url, username, allowed| {
+        // libgit2 keeps asking as long as credentials are returned
+        attempts += 1;
+        if attempts > 3 {
+            return Err(git2::Error::from_str("authentication failed"));
+        }
+
+        if allowed.is_ssh_key() {
+            return Cred::ssh_key_from_agent(username.unwrap_or("git"));
+        }
+        if allowed.is_user_pass_plaintext() {
+            return Cred::credential_helper(&git2::Config::open_default()?, url, username);
+        }
+        Cred::default()
+    });
+    callbacks
+}
+
+fn fetch_options<'a>() -> FetchOptions<'a> {
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks());
+    options
+}
+
+fn update_submodules(repo: &Repository) -> Result<()> {
+    for mut submodule in repo.submodules()? {
+        let mut options = SubmoduleUpdateOptions::new();
+        options.fetch(fetch_options());
+        submodule.update(true, Some(&mut options)).context(format!(
+            "Failed to update submodule {}",
+            submodule.path().display()
+        ))?;
+        update_submodules(&submodule.open()?)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::test_repo::{clone, commit, configure_user, git, setup};
+    use std::fs;
+
+    #[test]
+    fn clones_fetches_and_fast_forwards() {
+        let (remote, local) = setup("native-git");
+        let root = remote.parent().unwrap();
+        let native = root.join("native");
+
+        NativeGit
+            .clone_repo(remote.to_str().unwrap(), &native)
+            .unwrap();
+        configure_user(&native);
+        assert_eq!(fs::read_to_string(native.join("a")).unwrap(), "a");
+
+        commit(&local, "a", "remote");
+        git(&local, &["push", "-q"]);
+
+        NativeGit.fetch(&native).unwrap();
+        assert_eq!(NativeGit.divergence(&native).unwrap(), (0, 1));
+        NativeGit.fast_forward(&native).unwrap();
+        assert_eq!(fs::read_to_string(native.join("a")).unwrap(), "remote");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn errors_are_git_errors() {
+        let err = NativeGit.fetch(Path::new("/")).unwrap_err();
+        assert!(matches!(
+            crate::error::find(&err),
+            Some(MageError::Git { command, .. }) if command == "fetch"
+        ));
+        assert_eq!(crate::exit::code(&err), crate::exit::GIT_ERROR);
+    }
+
+    #[test]
+    fn status_commit_and_push() {
+        let (remote, local) = setup("native-git-push");
+        let root = remote.parent().unwrap();
+        fs::write(local.join("a"), "changed").unwrap();
+        fs::write(local.join("b"), "new").unwrap();
+
+        let kinds = NativeGit
+            .status(&local)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, vec![ChangeKind::Modified, ChangeKind::Untracked]);
+
+        NativeGit.commit_all(&local, "native").unwrap();
+        NativeGit.push(&local).unwrap();
+
+        assert!(NativeGit.status(&local).unwrap().is_empty());
+        assert_eq!(git(&remote, &["log", "-1", "--format=%s"]), "native");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn rebase_conflict_can_be_aborted() {
+        let (remote, local) = setup("native-git-rebase");
+        let root = remote.parent().unwrap();
+        let other = clone(root, "other");
+        commit(&other, "a", "remote");
+        git(&other, &["push", "-q"]);
+        commit(&local, "a", "local");
+        let head = git(&local, &["rev-parse", "HEAD"]);
+
+        NativeGit.fetch(&local).unwrap();
+        assert!(NativeGit.rebase(&local).is_err());
+        NativeGit.abort_rebase(&local).unwrap();
+
+        assert_eq!(git(&local, &["rev-parse", "HEAD"]), head);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn checks_out_branches_and_tags() {
+        let (remote, local) = setup("native-git-checkout");
+        let root = remote.parent().unwrap();
+        git(&local, &["tag", "v1"]);
+        git(&local, &["switch", "-q", "-c", "work"]);
+        commit(&local, "a", "work");
+        git(&local, &["push", "-q", "--tags", "origin", "work"]);
+        let other = clone(root, "other");
+        assert_eq!(NativeGit.branch_divergence(&other, "work").unwrap(), None);
+
+        NativeGit.checkout(&other, "work").unwrap();
+        assert_eq!(NativeGit.current_branch(&other).unwrap().unwrap(), "work");
+        assert_eq!(NativeGit.divergence(&other).unwrap(), (0, 0));
+        assert_eq!(
+            NativeGit.branch_divergence(&other, "work").unwrap(),
+            Some((0, 0))
+        );
+        assert_eq!(fs::read_to_string(other.join("a")).unwrap(), "work");
+
+        NativeGit.checkout(&other, "v1").unwrap();
+        assert_eq!(NativeGit.current_branch(&other).unwrap(), None);
+        assert_eq!(fs::read_to_string(other.join("a")).unwrap(), "a");
+        NativeGit.fetch(&other).unwrap();
+
+        assert!(NativeGit.checkout(&other, "missing").is_err());
+        assert_eq!(
+            NativeGit.remote_url(&other).unwrap(),
+            remote.to_str().unwrap()
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::{bail, ensure, Context, Result};
+use std::{path::Path, process::Command};
+use tracing::debug;
+
+use super::{Change, ChangeKind, GitBackend};
+use crate::error::MageError;
+
+/// Runs the `git` binary
+pub struct ProcessGit;
+
+impl GitBackend for ProcessGit {
+    fn clone_repo(&self, url: &str, dir: &Path) -> Result<()> {
+        let dir = dir.to_str().context("invalid clone directory")?;
+        git(Path::new("."), &["clone", "--recurse-submodules", url, dir])
+            .context(format!("Failed to clone repository {url}"))?;
+        Ok(())
+    }
+
+    fn checkout(&self, dir: &Path, reference: &str) -> Result<()> {
+        git(dir, &["checkout", "--quiet", reference])
+            .context(format!("Failed to check out {reference}"))?;
+        Ok(())
+    }
+
+    fn remote_url(&self, dir: &Path) -> Result<String> {
+        let url = git(dir, &["remote", "get-url", "origin"])?;
+        Ok(url.trim().to_string())
+    }
+
+    fn current_branch(&self, dir: &Path) -> Result<Option<String>> {
+        let branch = git(dir, &["branch", "--show-current"])?;
+        let branch = branch.trim();
+        Ok((!branch.is_empty()).then(|| branch.to_string()))
+    }
+
+    fn fetch(&self, dir: &Path) -> Result<()> {
+        git(dir, &["fetch", "--quiet"]).map(|_| ())
+    }
+
+    fn divergence(&self, dir: &Path) -> Result<(usize, usize)> {
+        ahead_behind(dir, "HEAD").context("Current branch has no upstream")
+    }
+
+    fn branch_divergence(&self, dir: &Path, branch: &str) -> Result<Option<(usize, usize)>> {
+        let local = format!("refs/heads/{branch}");
+        if run(dir, &["rev-parse", "--verify", "--quiet", &local]).is_err() {
+            return Ok(None);
+        }
+        let counts = ahead_behind(dir, branch).context(format!("{branch} has no upstream"))?;
+        Ok(Some(counts))
+    }
+
+    fn fast_forward(&self, dir: &Path) -> Result<()> {
+        git(dir, &["merge", "--ff-only", "--quiet", "@{u}"]).map(|_| ())
+    }
+
+    fn rebase(&self, dir: &Path) -> Result<()> {
+        git(dir, &["rebase", "--quiet", "@{u}"]).map(|_| ())
+    }
+
+    fn abort_rebase(&self, dir: &Path) -> Result<()> {
+        git(dir, &["rebase", "--abort"]).map(|_| ())
+    }
+
+    fn stash(&self, dir: &Path) -> Result<()> {
+        git(dir, &["stash", "push", "--quiet"]).map(|_| ())
+    }
+
+    fn stash_pop(&self, dir: &Path) -> Result<()> {
+        git(dir, &["stash", "pop", "--quiet"]).map(|_| ())
+    }
+
+    fn status(&self, dir: &Path) -> Result<Vec<Change>> {
+        let status = git(
+            dir,
+            &["status", "--porcelain", "-z", "--untracked-files=all"],
+        )?;
+        Ok(parse_status(&status, dir))
+    }
+
+    fn commit_all(&self, dir: &Path, message: &str) -> Result<()> {
+        git(dir, &["add", "--all"])?;
+        git(dir, &["commit", "--quiet", "-m", message]).map(|_| ())
+    }
+
+    fn push(&self, dir: &Path) -> Result<()> {
+        git(dir, &["push", "--quiet"]).map(|_| ())
+    }
+}
+
+/// Runs git in `dir` and returns its output, fails with the error git printed
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    run(dir, args).map_err(|source| {
+        MageError::Git {
+            command: args[0].into(),
+            dir: dir.into(),
+            source,
+        }
+        .into()
+    })
+}
+
+/// Number of commits `rev` is ahead and behind its upstream
+fn ahead_behind(dir: &Path, rev: &str) -> Result<(usize, usize)> {
+    let range = format!("{rev}...{rev}@{{u}}");
+    let counts = git(dir, &["rev-list", "--left-right", "--count", &range])?;
+
+    let mut counts = counts.split_whitespace().map(str::parse::<usize>);
+    match (counts.next(), counts.next()) {
+        (Some(Ok(ahead)), Some(Ok(behind))) => Ok((ahead, behind)),
+        _ => bail!("Unexpected output of git rev-list"),
+    }
+}
+
+fn run(dir: &Path, args: &[&str]) -> Result<String> {
+    debug!(?dir, ?args, "git");
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("Failed to run git")?;
+
+    ensure!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Parses the output of `git status --porcelain -z`
+fn parse_status(status: &str, dir: &Path) -> Vec<Change> {
+    let mut changes = vec![];
+    let mut fields = status.split('\0').filter(|f| !f.is_empty());
+
+    while let Some(field) = fields.next() {
+        let (code, path) = field.split_at(3.min(field.len()));
+        // Renames and copies are followed by the original path
+        if code.starts_with('R') || code.starts_with('C') {
+            fields.next();
+        }
+
+        let kind = match code.trim_end() {
+            "??" => ChangeKind::Untracked,
+            "DD" | "AU" | "UD" | "UA" | "DU" | "AA" | "UU" => ChangeKind::Conflicted,
+            _ => ChangeKind::Modified,
+        };
+        changes.push(Change {
+            path: dir.join(path),
+            kind,
+        });
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::test_repo::{clone, commit, git as run_git, setup};
+    use std::fs;
+
+    #[test]
+    fn parses_porcelain_status() {
+        let dir = Path::new("/dotfiles");
+        let status = " M nvim/init.lua\0?? bin/script\0R  new\0old\0UU conflict\0";
+        let changes = parse_status(status, dir);
+
+        let change = |path: &str, kind| Change {
+            path: dir.join(path),
+            kind,
+        };
+        assert_eq!(
+            changes,
+            vec![
+                change("nvim/init.lua", ChangeKind::Modified),
+                change("bin/script", ChangeKind::Untracked),
+                change("new", ChangeKind::Modified),
+                change("conflict", ChangeKind::Conflicted),
+            ]
+        );
+    }
+
+    #[test]
+    fn fetch_and_fast_forward() {
+        let (remote, local) = setup("process-git");
+        let root = remote.parent().unwrap();
+        let other = clone(root, "other");
+        commit(&other, "a", "remote");
+        run_git(&other, &["push", "-q"]);
+
+        ProcessGit.fetch(&local).unwrap();
+        assert_eq!(ProcessGit.divergence(&local).unwrap(), (0, 1));
+        ProcessGit.fast_forward(&local).unwrap();
+        assert_eq!(fs::read_to_string(local.join("a")).unwrap(), "remote");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn checks_out_branches_and_tags() {
+        let (remote, local) = setup("process-git-checkout");
+        let root = remote.parent().unwrap();
+        run_git(&local, &["tag", "v1"]);
+        run_git(&local, &["switch", "-q", "-c", "work"]);
+        commit(&local, "a", "work");
+        run_git(&local, &["push", "-q", "--tags", "origin", "work"]);
+        let other = clone(root, "other");
+        assert_eq!(ProcessGit.branch_divergence(&other, "work").unwrap(), None);
+
+        ProcessGit.checkout(&other, "work").unwrap();
+        assert_eq!(ProcessGit.current_branch(&other).unwrap().unwrap(), "work");
+        assert_eq!(ProcessGit.divergence(&other).unwrap(), (0, 0));
+        assert_eq!(
+            ProcessGit.branch_divergence(&other, "work").unwrap(),
+            Some((0, 0))
+        );
+        assert_eq!(fs::read_to_string(other.join("a")).unwrap(), "work");
+
+        ProcessGit.checkout(&other, "v1").unwrap();
+        assert_eq!(ProcessGit.current_branch(&other).unwrap(), None);
+        assert_eq!(fs::read_to_string(other.join("a")).unwrap(), "a");
+
+        assert!(ProcessGit.checkout(&other, "missing").is_err());
+        assert_eq!(
+            ProcessGit.remote_url(&other).unwrap(),
+            remote.to_str().unwrap()
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,26 +1,56 @@
-use clap::{command, Parser, Subcommand};
-use commands::Exec;
-use std::fmt::Debug;
+use clap::{Parser, Subcommand};
+use commands::{Exec, PullStrategy};
+use config::Config;
+use output::Format;
+use std::{fmt::Debug, process::ExitCode};
 use tracing::Level;
 mod commands;
+mod config;
 mod dotfiles;
+mod error;
+mod exit;
+mod git;
+mod output;
+mod packages;
+mod plan;
+mod state;
+mod summary;
 mod util;
 
-// TODO: Push command
 // TODO: More tests
 
 const DEBUG: bool = std::option_env!("MAGE_DEBUG").is_some();
 
-fn main() -> anyhow::Result<()> {
+fn main() -> ExitCode {
     let args = Args::parse();
+    let result = run(&args);
+    output::finish(&result);
 
-    if args.debug || DEBUG {
+    if let Err(e) = &result {
+        eprintln!("Error: {e:?}");
+        if let Some(hint) = error::hint(e) {
+            eprintln!("\nHint: {hint}");
+        }
+    }
+    exit::exit_code(&result)
+}
+
+fn run(args: &Args) -> anyhow::Result<()> {
+    // --output also applies to errors in the config, which sets the format otherwise
+    if let Some(format) = args.output {
+        output::init(format);
+    }
+    let config = Config::load()?;
+    output::init(config.output.format.unwrap_or_default());
+
+    if args.debug || DEBUG || config.output.debug {
         tracing_subscriber::fmt()
             .with_max_level(Level::DEBUG)
+            .with_writer(std::io::stderr)
             .init();
     }
 
-    args.command.execute()
+    args.execute(&config)
 }
 
 #[derive(Parser, Debug)]
@@ -29,6 +59,24 @@ struct Args {
     #[arg(short, long, default_value = "false", help = "Show debug information")]
     debug: bool,
 
+    #[arg(
+        long,
+        global = true,
+        help = "Show what would be done without changing anything"
+    )]
+    dry_run: bool,
+
+    #[arg(long, global = true, help = "Don't run the hooks in the magefile")]
+    no_hooks: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        help = "Format of the output [default: the format in the config, or text]"
+    )]
+    output: Option<Format>,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -42,39 +90,116 @@ enum Command {
         #[arg(
             short = 'p',
             long,
-            help = "Location of the dotfiles",
-            default_value = "~/.mage"
+            help = "Location of the dotfiles [default: the sources or the directory in the config, or ~/.mage]"
         )]
-        directory: String,
+        directory: Option<String>,
+        #[arg(long, help = "Only use the entries in this magefile profile")]
+        profile: Option<String>,
+    },
+    #[command(about = "Shows the link status of every entry in the magefile")]
+    Status {
+        #[arg(
+            short = 'p',
+            long,
+            help = "Location of the dotfiles [default: the sources or the directory in the config, or ~/.mage]"
+        )]
+        directory: Option<String>,
+    },
+    #[command(about = "Installs the packages the magefile requires")]
+    Install {
+        #[arg(
+            short = 'p',
+            long,
+            help = "Location of the dotfiles [default: the sources or the directory in the config, or ~/.mage]"
+        )]
+        directory: Option<String>,
+        #[arg(long, help = "Only use the entries in this magefile profile")]
+        profile: Option<String>,
     },
     #[command(about = "Link your dotfiles")]
     Link {
         #[arg(
-            help = "Location of the dotfiles, can also be repository url",
-            default_value = "~/.mage"
+            help = "Location of the dotfiles, can also be a repository url or shorthand like clone \
+                [default: the sources or the directory in the config, or ~/.mage]"
         )]
-        directory: String,
+        directory: Option<String>,
+        #[arg(
+            long,
+            help = "Path where a repository is cloned into [default: the directory in the config, or ~/.mage]"
+        )]
+        clone_dir: Option<String>,
+        #[arg(
+            short,
+            long,
+            visible_alias = "backup",
+            help = "Move conflicting files to a backup before linking"
+        )]
+        force: bool,
+        #[arg(long, help = "Only use the entries in this magefile profile")]
+        profile: Option<String>,
     },
+    #[command(about = "Restores the files backed up by link --force")]
+    Restore,
     #[command(about = "Clone your dotfiles repository")]
     Clone {
-        #[arg(help = "Repository to be cloned, either full url or <github-username>/<repository>")]
+        #[arg(
+            help = "Repository to be cloned: a url like https://host/user/repo.git or \
+                git@host:user/repo.git, <user>/<repository> on the host set in the config \
+                (GitHub by default) or a shorthand like gitlab:<user>/<repository> or \
+                sr.ht:~<user>/<repository>. Append #<ref> to check out a branch, tag or commit"
+        )]
         repository: String,
         #[arg(
             short,
             long,
-            help = "Path where dotfiles are cloned into",
-            default_value = "~/.mage"
+            help = "Path where dotfiles are cloned into [default: the directory in the config, or ~/.mage]"
+        )]
+        directory: Option<String>,
+        #[arg(
+            long = "ref",
+            help = "Branch, tag or commit to check out, later syncs stay on it"
+        )]
+        reference: Option<String>,
+    },
+    #[command(
+        about = "Commit and push changes in your dotfiles repository, or push local commits"
+    )]
+    Push {
+        #[arg(
+            short = 'p',
+            long,
+            help = "Location of the dotfiles [default: the directory in the config, or ~/.mage]"
+        )]
+        directory: Option<String>,
+        #[arg(
+            short,
+            long,
+            help = "Commit message, generated from the changed entries by default"
         )]
-        directory: String,
+        message: Option<String>,
+        #[arg(
+            short,
+            long,
+            help = "Also push untracked files outside of magefile entries"
+        )]
+        all: bool,
     },
     #[command(about = "Sync your dotfiles repository")]
     Sync {
         #[arg(
             short,
             long,
-            help = "Location of the dotfiles, can be empty if they are in the default location",
-            default_value = "~/.mage"
+            help = "Location of the dotfiles [default: the directory in the config, or ~/.mage]"
+        )]
+        directory: Option<String>,
+        #[arg(long, help = "Only use the entries in this magefile profile")]
+        profile: Option<String>,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t,
+            help = "What to do with uncommitted changes and local commits when pulling"
         )]
-        directory: String,
+        strategy: PullStrategy,
     },
 }
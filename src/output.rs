@@ -0,0 +1,342 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use crate::dotfiles::ProgramOptions;
+use crate::error;
+use crate::plan::{Action, Plan};
+use crate::summary::Summary;
+
+/// How the commands print their results
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// A line for everything that was done
+    #[default]
+    Text,
+    /// One JSON document on stdout once the command is done
+    Json,
+}
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+/// Everything reported so far in json mode
+static REPORT: Mutex<Report> = Mutex::new(Report::new());
+
+/// Sets the format for the rest of the run, text is used until this is called
+pub fn init(format: Format) {
+    FORMAT.get_or_init(|| format);
+}
+
+fn is_json() -> bool {
+    FORMAT.get() == Some(&Format::Json)
+}
+
+/// What happened to an entry or anything else a command touched
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Done,
+    /// Already in the wanted state
+    Unchanged,
+    Skipped,
+    Failed,
+}
+
+/// Result of one action
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Record {
+    /// Key of the magefile entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<PathBuf>,
+    pub action: &'static str,
+    pub outcome: Outcome,
+    /// More about the outcome, like why an entry was skipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// The error and its causes, outermost first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub error: Vec<String>,
+    /// What the user can do about the error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+    /// Line printed in text mode, failures are printed together by `show_errors`
+    #[serde(skip)]
+    text: Option<String>,
+}
+
+impl Record {
+    pub fn new(action: &'static str, outcome: Outcome) -> Self {
+        Self {
+            entry: None,
+            origin: None,
+            target: None,
+            action,
+            outcome,
+            detail: None,
+            error: vec![],
+            hint: None,
+            text: None,
+        }
+    }
+
+    pub fn failed(action: &'static str, error: &anyhow::Error) -> Self {
+        Self {
+            error: chain(error),
+            hint: error::hint(error),
+            ..Self::new(action, Outcome::Failed)
+        }
+    }
+
+    /// Entry, origin and target of `program`
+    pub fn program(self, program: &ProgramOptions) -> Self {
+        self.entry(&program.entry)
+            .origin(&program.origin_path)
+            .target(&program.target_path)
+    }
+
+    pub fn entry(mut self, entry: impl ToString) -> Self {
+        self.entry = Some(entry.to_string());
+        self
+    }
+
+    pub fn origin(mut self, origin: impl AsRef<Path>) -> Self {
+        self.origin = Some(origin.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn target(mut self, target: impl AsRef<Path>) -> Self {
+        self.target = Some(target.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn detail(mut self, detail: impl ToString) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    pub fn text(mut self, text: impl ToString) -> Self {
+        self.text = Some(text.to_string());
+        self
+    }
+}
+
+/// The JSON document of a run
+#[derive(Debug, Serialize)]
+struct Report {
+    /// Whether the command succeeded and no action failed
+    ok: bool,
+    results: Vec<Record>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<Counts>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plan: Option<PlanReport>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+    /// Error the command stopped with, and its causes
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    error: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<String>,
+}
+
+/// Counts of the [`Summary`] of the command
+#[derive(Debug, Default, Serialize)]
+struct Counts {
+    succeeded: usize,
+    skipped: usize,
+    failed: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct PlanReport {
+    actions: Vec<Action>,
+    /// Expected errors, each with its causes
+    errors: Vec<Vec<String>>,
+}
+
+impl Report {
+    const fn new() -> Self {
+        Self {
+            ok: true,
+            results: vec![],
+            summary: None,
+            plan: None,
+            warnings: vec![],
+            error: vec![],
+            hint: None,
+        }
+    }
+
+    fn add_plan(&mut self, plan: Plan) {
+        let report = self.plan.get_or_insert_with(Default::default);
+        report.actions.extend(plan.actions);
+        report.errors.extend(plan.errors.iter().map(chain));
+    }
+
+    fn finish(&mut self, result: &anyhow::Result<()>) {
+        if let Err(e) = result {
+            self.error = chain(e);
+            self.hint = error::hint(e);
+        }
+        let failed = self.results.iter().any(|r| r.outcome == Outcome::Failed);
+        self.ok = result.is_ok() && !failed;
+    }
+}
+
+/// Prints `record` in text mode, keeps it for the JSON document otherwise
+pub fn report(record: Record) {
+    if is_json() {
+        REPORT.lock().unwrap().results.push(record);
+    } else if let Some(text) = record.text {
+        println!("{text}");
+    }
+}
+
+/// Prints `text` in text mode only, for headers and summaries the records cover
+pub fn print(text: impl Display) {
+    if !is_json() {
+        print!("{text}");
+    }
+}
+
+pub fn warn(warning: impl Display) {
+    if is_json() {
+        REPORT.lock().unwrap().warnings.push(warning.to_string());
+    } else {
+        eprintln!("Warning: {warning}");
+    }
+}
+
+/// Adds the counts of `summary` to the JSON document
+pub fn summary(summary: &Summary) {
+    if !is_json() {
+        return;
+    }
+
+    let mut report = REPORT.lock().unwrap();
+    let counts = report.summary.get_or_insert_with(Default::default);
+    counts.succeeded += summary.succeeded;
+    counts.skipped += summary.skipped;
+    counts.failed += summary.failed;
+}
+
+/// Shows the plan of a `--dry-run`
+pub fn plan(plan: Plan) {
+    if is_json() {
+        REPORT.lock().unwrap().add_plan(plan);
+    } else {
+        print!("{plan}");
+    }
+}
+
+/// Whether errors are only shown in the JSON document
+pub fn errors_in_report() -> bool {
+    is_json()
+}
+
+/// Prints the JSON document with the result of the command in json mode
+pub fn finish(result: &anyhow::Result<()>) {
+    if !is_json() {
+        return;
+    }
+
+    let mut report = REPORT.lock().unwrap();
+    report.finish(result);
+    match serde_json::to_string_pretty(&*report) {
+        Ok(json) => println!("{json}"),
+        Err(e) => eprintln!("Failed to serialize the results: {e}"),
+    }
+}
+
+fn chain(error: &anyhow::Error) -> Vec<String> {
+    error.chain().map(ToString::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn json_report() {
+        let mut report = Report::new();
+        report.results.push(
+            Record::new("link", Outcome::Done)
+                .origin("/dotfiles/.bashrc")
+                .target("/home/user/.bashrc")
+                .text("/dotfiles/.bashrc linked ✔️"),
+        );
+        let error = Err::<(), _>(anyhow!("permission denied"))
+            .context("delete link")
+            .unwrap_err();
+        report
+            .results
+            .push(Record::failed("clean", &error).target("/home/user/.vimrc"));
+        report.finish(&Ok(()));
+
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "ok": false,
+                "results": [
+                    {
+                        "origin": "/dotfiles/.bashrc",
+                        "target": "/home/user/.bashrc",
+                        "action": "link",
+                        "outcome": "done",
+                    },
+                    {
+                        "target": "/home/user/.vimrc",
+                        "action": "clean",
+                        "outcome": "failed",
+                        "error": ["delete link", "permission denied"],
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn json_plan() {
+        let mut report = Report::new();
+        let mut plan = Plan::default();
+        plan.push(Action::DeleteSymlink("/home/user/.bashrc".into()));
+        plan.push(Action::Symlink {
+            origin: "/dotfiles/.vimrc".into(),
+            target: "/home/user/.vimrc".into(),
+        });
+        plan.errors.push(anyhow!("/dotfiles/nvim does not exist"));
+        report.add_plan(plan);
+        let not_found = error::MageError::MagefileNotFound {
+            dir: "/dotfiles".into(),
+        };
+        report.finish(&Err(not_found.into()));
+
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "ok": false,
+                "results": [],
+                "plan": {
+                    "actions": [
+                        { "delete_symlink": "/home/user/.bashrc" },
+                        { "symlink": { "origin": "/dotfiles/.vimrc", "target": "/home/user/.vimrc" } },
+                    ],
+                    "errors": [["/dotfiles/nvim does not exist"]],
+                },
+                "error": ["Magefile not found in /dotfiles"],
+                "hint": "Run `mage init` in /dotfiles or pass the dotfiles directory",
+            })
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::{ensure, Context, Result};
+use std::process::Command;
+use tracing::debug;
+
+use crate::util::on_path;
+
+/// Installs packages for `mage install`
+pub trait PackageManager {
+    /// Key of the package names for this manager in the magefile, like `apt`
+    fn name(&self) -> &'static str;
+    fn install(&self, packages: &[String]) -> Result<()>;
+}
+
+/// Package managers mage can drive, in the order they are detected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum System {
+    Brew,
+    Apt,
+    Dnf,
+    Pacman,
+    Cargo,
+}
+
+impl System {
+    const ALL: [System; 5] = [
+        System::Brew,
+        System::Apt,
+        System::Dnf,
+        System::Pacman,
+        System::Cargo,
+    ];
+
+    fn binary(&self) -> &'static str {
+        match self {
+            Self::Brew => "brew",
+            Self::Apt => "apt-get",
+            Self::Dnf => "dnf",
+            Self::Pacman => "pacman",
+            Self::Cargo => "cargo",
+        }
+    }
+
+    /// Command line that installs `packages`, without `sudo`
+    fn command(&self, packages: &[String]) -> Vec<String> {
+        let args: &[&str] = match self {
+            Self::Brew => &["brew", "install"],
+            Self::Apt => &["apt-get", "install", "--yes"],
+            Self::Dnf => &["dnf", "install", "--assumeyes"],
+            Self::Pacman => &["pacman", "--sync", "--needed", "--noconfirm"],
+            Self::Cargo => &["cargo", "install"],
+        };
+        args.iter()
+            .map(ToString::to_string)
+            .chain(packages.iter().cloned())
+            .collect()
+    }
+
+    /// System package managers need root, brew and cargo install for the user
+    fn needs_root(&self) -> bool {
+        matches!(self, Self::Apt | Self::Dnf | Self::Pacman)
+    }
+}
+
+impl PackageManager for System {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Apt => "apt",
+            system => system.binary(),
+        }
+    }
+
+    fn install(&self, packages: &[String]) -> Result<()> {
+        let mut command = self.command(packages);
+        if self.needs_root() && !is_root() {
+            command.insert(0, "sudo".to_string());
+        }
+        debug!(?command, "install");
+
+        // Not captured, the package manager may ask for a password or confirmation
+        let status = Command::new(&command[0])
+            .args(&command[1..])
+            .status()
+            .with_context(|| format!("run {}", command[0]))?;
+        ensure!(
+            status.success(),
+            "`{}` failed with {status}",
+            command.join(" ")
+        );
+        Ok(())
+    }
+}
+
+/// The first package manager on `PATH`, cargo is only used without a system one
+pub fn detect() -> Option<Box<dyn PackageManager>> {
+    detect_with(on_path).map(|system| Box::new(system) as Box<dyn PackageManager>)
+}
+
+fn detect_with(on_path: impl Fn(&str) -> bool) -> Option<System> {
+    System::ALL
+        .into_iter()
+        .find(|system| on_path(system.binary()))
+}
+
+fn is_root() -> bool {
+    Command::new("id")
+        .arg("-u")
+        .output()
+        .is_ok_and(|output| output.stdout.trim_ascii() == b"0")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_first_available() {
+        assert_eq!(
+            detect_with(|bin| ["cargo", "pacman"].contains(&bin)),
+            Some(System::Pacman)
+        );
+        assert_eq!(detect_with(|bin| bin == "cargo"), Some(System::Cargo));
+        assert_eq!(detect_with(|_| false), None);
+    }
+
+    #[test]
+    fn install_commands() {
+        let packages = vec!["neovim".to_string(), "ripgrep".to_string()];
+
+        assert_eq!(
+            System::Apt.command(&packages).join(" "),
+            "apt-get install --yes neovim ripgrep"
+        );
+        assert_eq!(System::Apt.name(), "apt");
+        assert_eq!(
+            System::Brew.command(&packages[..1]),
+            ["brew", "install", "neovim"]
+        );
+        assert!(!System::Cargo.needs_root());
+    }
+}
@@ -0,0 +1,163 @@
+use serde::Serialize;
+use std::{fmt::Display, path::PathBuf};
+
+/// Something mage would do when not running with `--dry-run`
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    CreateDir(PathBuf),
+    CreateFile(PathBuf),
+    Symlink {
+        origin: PathBuf,
+        target: PathBuf,
+    },
+    Hardlink {
+        origin: PathBuf,
+        target: PathBuf,
+    },
+    Copy {
+        origin: PathBuf,
+        target: PathBuf,
+    },
+    DeleteSymlink(PathBuf),
+    DeleteFile(PathBuf),
+    /// An empty directory mage created
+    DeleteDir(PathBuf),
+    Render {
+        origin: PathBuf,
+        output: PathBuf,
+    },
+    Backup {
+        target: PathBuf,
+        backup: PathBuf,
+    },
+    Restore {
+        backup: PathBuf,
+        target: PathBuf,
+    },
+    /// Arguments passed to git
+    Git(Vec<String>),
+    /// Hook from the magefile
+    Hook(String),
+    Install {
+        manager: String,
+        packages: Vec<String>,
+    },
+}
+
+impl Action {
+    pub fn git<S: ToString>(args: &[S]) -> Self {
+        Self::Git(args.iter().map(ToString::to_string).collect())
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CreateDir(path) => write!(f, "create directory {}", path.display()),
+            Self::CreateFile(path) => write!(f, "create file {}", path.display()),
+            Self::Symlink { origin, target } => {
+                write!(f, "symlink {} -> {}", target.display(), origin.display())
+            }
+            Self::Hardlink { origin, target } => {
+                write!(f, "hardlink {} to {}", target.display(), origin.display())
+            }
+            Self::Copy { origin, target } => {
+                write!(f, "copy {} to {}", origin.display(), target.display())
+            }
+            Self::DeleteSymlink(path) => write!(f, "delete symlink {}", path.display()),
+            Self::DeleteFile(path) => write!(f, "delete file {}", path.display()),
+            Self::DeleteDir(path) => write!(f, "delete directory {}", path.display()),
+            Self::Render { origin, output } => {
+                write!(f, "render {} to {}", origin.display(), output.display())
+            }
+            Self::Backup { target, backup } => {
+                write!(f, "move {} to {}", target.display(), backup.display())
+            }
+            Self::Restore { backup, target } => {
+                write!(f, "move {} back to {}", backup.display(), target.display())
+            }
+            Self::Git(args) => write!(f, "git {}", args.join(" ")),
+            Self::Hook(hook) => write!(f, "run {hook}"),
+            Self::Install { manager, packages } => {
+                write!(f, "install {} with {manager}", packages.join(", "))
+            }
+        }
+    }
+}
+
+/// Actions and expected errors of a `--dry-run`
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub actions: Vec<Action>,
+    pub errors: Vec<anyhow::Error>,
+}
+
+impl Plan {
+    /// Adds `action` unless it is already planned, e.g. the same parent directory for two links
+    pub fn push(&mut self, action: Action) {
+        if !self.actions.contains(&action) {
+            self.actions.push(action);
+        }
+    }
+
+    pub fn extend(&mut self, other: Plan) {
+        other.actions.into_iter().for_each(|a| self.push(a));
+        self.errors.extend(other.errors);
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.actions.is_empty() {
+            writeln!(f, "Nothing to do ✔️")?;
+        } else {
+            writeln!(f, "Plan:")?;
+        }
+
+        for action in &self.actions {
+            writeln!(f, "  {action}")?;
+        }
+
+        if !self.errors.is_empty() {
+            writeln!(f, "Would fail:")?;
+        }
+
+        for error in &self.errors {
+            writeln!(f, "  {error}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_plan() {
+        let mut plan = Plan::default();
+        plan.push(Action::git(&["-C", "/dotfiles", "pull"]));
+        plan.push(Action::Symlink {
+            origin: "/dotfiles/.bashrc".into(),
+            target: "/home/user/.bashrc".into(),
+        });
+        plan.errors
+            .push(anyhow::anyhow!("/dotfiles/nvim does not exist"));
+
+        let expected = "\
+Plan:
+  git -C /dotfiles pull
+  symlink /home/user/.bashrc -> /dotfiles/.bashrc
+Would fail:
+  /dotfiles/nvim does not exist
+";
+        assert_eq!(plan.to_string(), expected);
+    }
+
+    #[test]
+    fn display_empty_plan() {
+        assert_eq!(Plan::default().to_string(), "Nothing to do ✔️\n");
+    }
+}
@@ -0,0 +1,358 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::debug;
+
+use crate::dotfiles::LinkMode;
+use crate::util::{hash, same_file, FullPath};
+
+/// Location of the state file when `XDG_STATE_HOME` is not set
+const DEFAULT_STATE_DIR: &str = "~/.local/state";
+
+/// One symlink that was created by mage
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    /// Path of the config file or folder in the dotfiles
+    pub origin: PathBuf,
+    /// Path of the symlink
+    pub target: PathBuf,
+    /// Seconds since unix epoch when the symlink was created
+    pub created_at: u64,
+    /// Set if the symlink points to a rendered template instead of the origin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rendered: Option<Rendered>,
+    #[serde(default, skip_serializing_if = "LinkMode::is_symlink")]
+    pub mode: LinkMode,
+    /// Hash of the content mage copied to the target in copy mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Parent directories of the target that mage created for it, moved to
+    /// [`State`] when the link is recorded
+    #[serde(skip)]
+    pub created_dirs: Vec<PathBuf>,
+}
+
+/// Rendered template that a link points to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rendered {
+    pub path: PathBuf,
+    /// Hash of the content mage wrote
+    pub hash: String,
+}
+
+impl Rendered {
+    /// Whether the file still has the content mage wrote
+    pub fn is_unchanged(&self) -> bool {
+        fs::read(&self.path).is_ok_and(|content| hash(content) == self.hash)
+    }
+}
+
+impl Link {
+    /// Path the symlink points to
+    pub fn source(&self) -> &Path {
+        match &self.rendered {
+            Some(rendered) => &rendered.path,
+            None => &self.origin,
+        }
+    }
+
+    /// Whether the target is still what mage created: a symlink or hard link to the
+    /// source, or a copy with the content mage wrote
+    pub fn is_linked(&self) -> bool {
+        match self.mode {
+            LinkMode::Symlink => {
+                fs::read_link(&self.target).is_ok_and(|source| source == self.source())
+            }
+            LinkMode::Hardlink => same_file(&self.target, self.source()),
+            LinkMode::Copy => {
+                !self.target.is_symlink()
+                    && fs::read(&self.target).is_ok_and(|content| Some(hash(content)) == self.hash)
+            }
+        }
+    }
+
+    pub fn new(origin: &FullPath, target: &FullPath) -> Self {
+        Self {
+            origin: origin.as_path().to_path_buf(),
+            target: target.as_path().to_path_buf(),
+            created_at: now(),
+            rendered: None,
+            mode: LinkMode::Symlink,
+            hash: None,
+            created_dirs: vec![],
+        }
+    }
+}
+
+/// A file or directory that was moved away to make room for a symlink
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Backup {
+    /// Original location of the file
+    pub target: PathBuf,
+    /// Where the file was moved to
+    pub backup: PathBuf,
+    /// Seconds since unix epoch when the backup was made
+    pub created_at: u64,
+}
+
+/// A dotfiles repository mage has cloned
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkout {
+    /// Directory the repository was cloned to
+    pub path: PathBuf,
+    pub url: String,
+    /// Branch, tag or commit that sync stays on, the default branch if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+}
+
+/// Manifest of every symlink and backup mage has created, persisted between runs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    links: Vec<Link>,
+    #[serde(default)]
+    backups: Vec<Backup>,
+    #[serde(default)]
+    checkouts: Vec<Checkout>,
+    /// Directories mage created for the targets, removed by clean once they're empty
+    #[serde(default)]
+    dirs: Vec<PathBuf>,
+}
+
+impl State {
+    /// Loads the state from the default location
+    pub fn load() -> Result<Self> {
+        Self::load_from(default_state_path())
+    }
+
+    /// Loads the state from `path`, an empty state is returned if the file does not exist
+    pub fn load_from<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path: PathBuf = path.into();
+
+        if !path.exists() {
+            debug!(path = ?path, "no state file");
+            return Ok(Self {
+                path,
+                ..Default::default()
+            });
+        }
+
+        let content = fs::read_to_string(&path).context("read state file")?;
+        let mut state: State = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse state file {}", path.display()))?;
+        state.path = path;
+
+        Ok(state)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("create state directory")?;
+        }
+
+        let content = toml::to_string(self).context("serialize state")?;
+        fs::write(&self.path, content).context("write state file")?;
+        debug!(path = ?self.path, "state saved");
+
+        Ok(())
+    }
+
+    /// Records a link, replacing any previous record with the same target
+    pub fn insert(&mut self, mut link: Link) {
+        self.remove(&link.target);
+        for dir in link.created_dirs.drain(..) {
+            if !self.dirs.contains(&dir) {
+                self.dirs.push(dir);
+            }
+        }
+        self.links.push(link);
+    }
+
+    pub fn remove(&mut self, target: &Path) {
+        self.links.retain(|l| l.target != target);
+    }
+
+    pub fn link(&self, target: &Path) -> Option<&Link> {
+        self.links.iter().find(|l| l.target == target)
+    }
+
+    pub fn insert_backup(&mut self, backup: Backup) {
+        self.backups.push(backup);
+    }
+
+    pub fn remove_backup(&mut self, backup: &Backup) {
+        self.backups.retain(|b| b != backup);
+    }
+
+    pub fn backups(&self) -> Vec<Backup> {
+        self.backups.clone()
+    }
+
+    /// Records a checkout, replacing any previous record of the same directory
+    pub fn insert_checkout(&mut self, checkout: Checkout) {
+        self.checkouts.retain(|c| c.path != checkout.path);
+        self.checkouts.push(checkout);
+    }
+
+    pub fn checkout(&self, path: &Path) -> Option<&Checkout> {
+        self.checkouts.iter().find(|c| c.path == path)
+    }
+
+    /// Directories mage created that are empty once the `removed` paths are gone,
+    /// deepest first
+    pub fn emptied_dirs(&self, removed: &[PathBuf]) -> Vec<PathBuf> {
+        let mut dirs = self.dirs.clone();
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+
+        let mut emptied: Vec<PathBuf> = vec![];
+        for dir in dirs {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            let empty = entries.flatten().all(|entry| {
+                let path = entry.path();
+                removed.contains(&path) || emptied.contains(&path)
+            });
+            if empty {
+                emptied.push(dir);
+            }
+        }
+
+        emptied
+    }
+
+    /// Deletes the directories mage created that are empty now and forgets them,
+    /// returns the deleted directories
+    pub fn remove_empty_dirs(&mut self) -> Result<Vec<PathBuf>> {
+        let emptied = self.emptied_dirs(&[]);
+        for dir in &emptied {
+            fs::remove_dir(dir).with_context(|| format!("delete directory {}", dir.display()))?;
+            debug!(?dir, "delete");
+        }
+
+        self.dirs
+            .retain(|dir| dir.is_dir() && !emptied.contains(dir));
+        Ok(emptied)
+    }
+
+    /// All of the links whose origin is inside `dotfiles_path`
+    pub fn links_from(&self, dotfiles_path: &Path) -> Vec<Link> {
+        self.links
+            .iter()
+            .filter(|l| l.origin.starts_with(dotfiles_path))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Seconds since unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn default_state_path() -> PathBuf {
+    let state_dir = std::env::var("XDG_STATE_HOME").unwrap_or(DEFAULT_STATE_DIR.to_string());
+    let mut path = FullPath::from(state_dir).path();
+    path.push("mage");
+    path.push("state.toml");
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(origin: &str, target: &str) -> Link {
+        Link::new(&origin.into(), &target.into())
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = PathBuf::from("/tmp/mage-state-test/state.toml");
+        fs::remove_dir_all("/tmp/mage-state-test").unwrap_or_default();
+
+        let mut state = State::load_from(&path).unwrap();
+        state.insert(link("/dotfiles/a", "/tmp/a"));
+        let mut rendered = link("/dotfiles/b", "/tmp/b");
+        rendered.rendered = Some(Rendered {
+            path: "/rendered/b".into(),
+            hash: "abc".into(),
+        });
+        state.insert(rendered);
+        state.insert_checkout(Checkout {
+            path: "/tmp/dotfiles".into(),
+            url: "git@github.com:user/dotfiles.git".into(),
+            reference: Some("v1".into()),
+        });
+        state.save().unwrap();
+
+        let loaded = State::load_from(&path).unwrap();
+        fs::remove_dir_all("/tmp/mage-state-test").unwrap_or_default();
+
+        assert_eq!(loaded.links, state.links);
+        assert_eq!(loaded.checkouts, state.checkouts);
+    }
+
+    #[test]
+    fn insert_replaces_same_target() {
+        let mut state = State::default();
+        state.insert(link("/dotfiles/a", "/tmp/a"));
+        state.insert(link("/dotfiles/b", "/tmp/a"));
+
+        assert_eq!(state.links.len(), 1);
+        assert_eq!(state.links[0].origin, PathBuf::from("/dotfiles/b"));
+    }
+
+    #[test]
+    fn removes_emptied_dirs() {
+        let root = PathBuf::from("/tmp/mage-state-dirs");
+        fs::remove_dir_all(&root).unwrap_or_default();
+        let (outer, inner) = (root.join("nvim"), root.join("nvim/lua"));
+        fs::create_dir_all(&inner).unwrap();
+        fs::write(inner.join("plugins.lua"), "").unwrap();
+
+        let mut state = State::default();
+        let mut created = link("/dotfiles/nvim/lua/plugins.lua", "/tmp/plugins.lua");
+        created.created_dirs = vec![inner.clone(), outer.clone()];
+        state.insert(created.clone());
+        state.insert(created);
+        assert_eq!(state.dirs.len(), 2);
+
+        let removed = [inner.join("plugins.lua")];
+        assert_eq!(
+            state.emptied_dirs(&removed),
+            vec![inner.clone(), outer.clone()]
+        );
+        assert!(state.remove_empty_dirs().unwrap().is_empty());
+
+        fs::remove_file(inner.join("plugins.lua")).unwrap();
+        assert_eq!(
+            state.remove_empty_dirs().unwrap(),
+            vec![inner, outer.clone()]
+        );
+        assert!(!outer.exists());
+        assert!(state.dirs.is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn links_from_dotfiles_path() {
+        let mut state = State::default();
+        state.insert(link("/dotfiles/a", "/tmp/a"));
+        state.insert(link("/other/b", "/tmp/b"));
+
+        let links = state.links_from(Path::new("/dotfiles"));
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].target, PathBuf::from("/tmp/a"));
+    }
+}
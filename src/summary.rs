@@ -0,0 +1,104 @@
+use anyhow::Result;
+use std::fmt::Display;
+
+use crate::exit::PartialFailure;
+use crate::output::{self, Outcome};
+use crate::util::show_errors;
+
+/// What a command did to its entries, with the errors of the ones that failed
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub succeeded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub errors: Vec<anyhow::Error>,
+}
+
+impl Summary {
+    pub fn add(&mut self, result: Result<Outcome>) {
+        match result {
+            Ok(Outcome::Done | Outcome::Unchanged) => self.succeeded += 1,
+            Ok(Outcome::Skipped) => self.skipped += 1,
+            Ok(Outcome::Failed) => self.failed += 1,
+            Err(e) => {
+                self.failed += 1;
+                self.errors.push(e);
+            }
+        }
+    }
+
+    pub fn extend(&mut self, other: Summary) {
+        self.succeeded += other.succeeded;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+        self.errors.extend(other.errors);
+    }
+
+    pub fn total(&self) -> usize {
+        self.succeeded + self.skipped + self.failed
+    }
+
+    /// Shows the errors, then fails with a [`PartialFailure`] if any entry failed
+    pub fn finish(self) -> Result<()> {
+        show_errors(&self.errors);
+        output::summary(&self);
+
+        if self.failed > 0 {
+            return Err(PartialFailure {
+                failed: self.failed,
+                total: self.total(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl FromIterator<Result<Outcome>> for Summary {
+    fn from_iter<T: IntoIterator<Item = Result<Outcome>>>(iter: T) -> Self {
+        let mut summary = Summary::default();
+        iter.into_iter().for_each(|result| summary.add(result));
+        summary
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} succeeded, {} skipped, {} failed",
+            self.succeeded, self.skipped, self.failed
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn counts_outcomes() {
+        let mut summary: Summary = [
+            Ok(Outcome::Done),
+            Ok(Outcome::Unchanged),
+            Ok(Outcome::Skipped),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(summary.to_string(), "2 succeeded, 1 skipped, 0 failed");
+        assert!(Summary::default().finish().is_ok());
+
+        summary.extend([Err(anyhow!("a does not exist"))].into_iter().collect());
+        assert_eq!(summary.failed, 1);
+        let err = summary.finish().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PartialFailure>(),
+            Some(&PartialFailure {
+                failed: 1,
+                total: 4
+            })
+        );
+    }
+}
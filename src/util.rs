@@ -1,15 +1,28 @@
+use regex::Regex;
+use sha2::{Digest, Sha256};
 use std::{
     fmt::Display,
+    fs, io,
+    os::unix::fs::{symlink, MetadataExt},
     path::{Path, PathBuf},
 };
 
-/// Displays errors if there are any
-pub(crate) fn show_errors(result: Vec<anyhow::Result<()>>) {
+/// Location of the mage data when `XDG_DATA_HOME` is not set
+const DEFAULT_DATA_DIR: &str = "~/.local/share";
+
+/// Displays errors if there are any. With json output they are in the records of
+/// the failed actions instead
+pub(crate) fn show_errors(errors: &[anyhow::Error]) {
+    if crate::output::errors_in_report() {
+        return;
+    }
+
     let mut msg = String::new();
 
-    for res in result {
-        if let Err(e) = res {
-            msg.push_str(format!("{}\n", e).as_str());
+    for e in errors {
+        msg.push_str(format!("{}\n", e).as_str());
+        if let Some(hint) = crate::error::hint(e) {
+            msg.push_str(format!("  Hint: {hint}\n").as_str());
         }
     }
 
@@ -18,6 +31,87 @@ pub(crate) fn show_errors(result: Vec<anyhow::Result<()>>) {
     }
 }
 
+/// Matches `s` against a glob pattern where `*` matches any characters and `?` one character
+pub(crate) fn glob_match(pattern: &str, s: &str) -> bool {
+    let re = regex::escape(pattern)
+        .replace(r"\*", ".*")
+        .replace(r"\?", ".");
+    Regex::new(&format!("^{re}$")).is_ok_and(|re| re.is_match(s))
+}
+
+/// Whether an executable `name` is in one of the directories in `PATH`
+pub(crate) fn on_path(name: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+
+    std::env::split_paths(&path)
+        .any(|dir| fs::metadata(dir.join(name)).is_ok_and(|m| m.is_file() && m.mode() & 0o111 != 0))
+}
+
+/// Directory for files generated by mage, e.g. `~/.local/share/mage`
+pub(crate) fn data_dir() -> PathBuf {
+    let data_dir = std::env::var("XDG_DATA_HOME").unwrap_or(DEFAULT_DATA_DIR.to_string());
+    let mut path = FullPath::from(data_dir).path();
+    path.push("mage");
+    path
+}
+
+/// Mirrors the full `path` inside `dir`, e.g. `/home/user/.bashrc` becomes
+/// `<dir>/home/user/.bashrc`
+pub(crate) fn mirror_path(path: &Path, dir: &Path) -> PathBuf {
+    let relative = path.strip_prefix("/").unwrap_or(path);
+    dir.join(relative)
+}
+
+/// Whether `a` is a hard link to the same file as `b`, `a` must not be a symlink
+pub(crate) fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::symlink_metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Moves `from` to `to`, across filesystems it's copied and removed instead
+pub(crate) fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_all(from, to)?;
+            if from.is_dir() && !from.is_symlink() {
+                fs::remove_dir_all(from)
+            } else {
+                fs::remove_file(from)
+            }
+        }
+        result => result,
+    }
+}
+
+/// Copies a file, a symlink or a directory with everything in it
+fn copy_all(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.is_symlink() {
+        symlink(fs::read_link(from)?, to)
+    } else if metadata.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        fs::set_permissions(to, metadata.permissions())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+/// Hex encoded sha256 of `content`
+pub(crate) fn hash(content: impl AsRef<[u8]>) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
 /// Expands `~` to the home directory
 pub(crate) fn get_full_path<P: Into<PathBuf>>(path: P) -> PathBuf {
     let path: PathBuf = path.into();
@@ -118,8 +212,14 @@ pub(crate) mod test_context {
             let target_path = PathBuf::from(unique_tmp_path());
 
             let opts = ProgramOptions {
+                entry: "test-dotfiles".into(),
                 origin_path: dotfiles_path.into(),
                 target_path: target_path.clone().into(),
+                conditions: Default::default(),
+                template: None,
+                mode: Default::default(),
+                hooks: vec![],
+                requires: vec![],
             };
 
             Ctx {
@@ -156,4 +256,50 @@ mod test {
         let path = get_full_path("/tmp/test");
         assert_eq!(path, expected);
     }
+
+    #[test]
+    fn test_hash() {
+        assert_eq!(
+            hash("mage"),
+            "6661473579cc99d9243ac99890127a568835bc3a983f542a581823998653153b"
+        );
+    }
+
+    #[test]
+    fn test_on_path() {
+        assert!(on_path("sh"));
+        assert!(!on_path("mage-not-a-binary"));
+    }
+
+    #[test]
+    fn test_copy_all() {
+        let root = Path::new("/tmp/mage-copy-all");
+        fs::remove_dir_all(root).unwrap_or_default();
+        let from = root.join("from");
+        fs::create_dir_all(from.join("nested")).unwrap();
+        fs::write(from.join("nested/file"), "a").unwrap();
+        symlink("nested/file", from.join("link")).unwrap();
+
+        let to = root.join("to");
+        copy_all(&from, &to).unwrap();
+        assert_eq!(fs::read_to_string(to.join("nested/file")).unwrap(), "a");
+        assert_eq!(
+            fs::read_link(to.join("link")).unwrap(),
+            Path::new("nested/file")
+        );
+
+        move_path(&to, &root.join("moved")).unwrap();
+        assert!(!to.exists());
+        assert!(root.join("moved/nested/file").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("work-*", "work-laptop"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("host-?", "host-1"));
+        assert!(!glob_match("work-*", "home-work-laptop"));
+        assert!(!glob_match("a.b", "axb"));
+    }
 }